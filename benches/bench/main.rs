@@ -4,7 +4,7 @@ use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use phash::{chd::*, value::*, *};
 use rand::{distributions::Alphanumeric, prelude::SliceRandom, Rng};
 
-const BENCH_FILE: &'static str = "./bench.bin";
+const BENCH_FILE: &str = "./bench.bin";
 const DEFAULT_LEN: usize = 50_0000;
 
 fn init_data(cfg: CHDGeneratorConfig, len: usize) -> Vec<(String, String)> {
@@ -35,7 +35,7 @@ fn init_data(cfg: CHDGeneratorConfig, len: usize) -> Vec<(String, String)> {
         .iter()
         .map(|v| (v.0.as_str(), v.1.as_bytes()))
         .collect();
-    serializer.write_to_file(&tmp_keys, BENCH_FILE);
+    serializer.write_to_file(&tmp_keys, BENCH_FILE).unwrap();
     keys
 }
 
@@ -48,7 +48,7 @@ fn test_lookup(c: &mut Criterion) {
     );
     let mut rng = rand::thread_rng();
     keys.shuffle(&mut rng);
-    let size = deserializer.load_from_mmap_file(BENCH_FILE);
+    let size = deserializer.load_from_mmap_file(BENCH_FILE).unwrap();
     println!("{:?} size {}KiB", config, size / 1024);
 
     let mut idx = 0usize;
@@ -56,7 +56,7 @@ fn test_lookup(c: &mut Criterion) {
         b.iter(|| unsafe {
             for _ in 0..1000 {
                 let (k, _) = &keys.get_unchecked(idx % keys.len());
-                let value: &str = std::str::from_utf8_unchecked(deserializer.get(k));
+                let value: &str = std::str::from_utf8_unchecked(deserializer.get(k).unwrap());
                 black_box(value);
                 idx += 1;
             }
@@ -75,7 +75,7 @@ fn test_lookup_seq(c: &mut Criterion) {
         DefaultHashValueReader::new(),
     );
 
-    let size = deserializer.load_from_mmap_file(BENCH_FILE);
+    let size = deserializer.load_from_mmap_file(BENCH_FILE).unwrap();
     println!("{:?} size {}KiB", config, size / 1024);
 
     let mut idx = 0usize;
@@ -83,7 +83,7 @@ fn test_lookup_seq(c: &mut Criterion) {
         b.iter(|| unsafe {
             for _ in 0..1000 {
                 let (k, _) = &keys.get_unchecked(idx % keys.len());
-                let value: &str = std::str::from_utf8_unchecked(deserializer.get(k));
+                let value: &str = std::str::from_utf8_unchecked(deserializer.get(k).unwrap());
                 black_box(value);
                 idx += 1;
             }
@@ -104,7 +104,7 @@ fn test_lookup_minimal(c: &mut Criterion) {
 
     let mut rng = rand::thread_rng();
     keys.shuffle(&mut rng);
-    let size = deserializer.load_from_mmap_file(BENCH_FILE);
+    let size = deserializer.load_from_mmap_file(BENCH_FILE).unwrap();
     println!("{:?} size {}KiB", config, size / 1024);
 
     let mut idx = 0usize;
@@ -112,7 +112,7 @@ fn test_lookup_minimal(c: &mut Criterion) {
         b.iter(|| unsafe {
             for _ in 0..1000 {
                 let (k, _) = &keys.get_unchecked(idx % keys.len());
-                let value: &str = std::str::from_utf8_unchecked(deserializer.get(k));
+                let value: &str = std::str::from_utf8_unchecked(deserializer.get(k).unwrap());
                 black_box(value);
                 idx += 1;
             }
@@ -123,6 +123,216 @@ fn test_lookup_minimal(c: &mut Criterion) {
     std::fs::remove_file(BENCH_FILE).unwrap();
 }
 
+fn lookup_int<H: Hasher>(c: &mut Criterion, name: &str) {
+    let mut keys: Vec<u64> = (0..DEFAULT_LEN as u64).map(|i| i * 7919).collect();
+    let kvs: Vec<(u64, &[u8])> = keys.iter().map(|k| (*k, &b"value"[..])).collect();
+    let mut serializer = PerfectHashMapSerializer::<H, _, _, _>::new(
+        CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(0.5f32)),
+        DefaultHashValueWriter::new(),
+    );
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    serializer.write_to(&kvs, &mut buffer).unwrap();
+    let mut deserializer = PerfectHashMapDeserializer::<H, u64, _, _>::new(
+        CHDReader::new(),
+        DefaultHashValueReader::new(),
+    );
+    deserializer.load_from_buffer(buffer.into_inner()).unwrap();
+    keys.shuffle(&mut rand::thread_rng());
+
+    let mut idx = 0usize;
+    c.bench_function(name, |b| {
+        b.iter(|| unsafe {
+            for _ in 0..1000 {
+                let k = keys.get_unchecked(idx % keys.len());
+                black_box(deserializer.get(k).unwrap());
+                idx += 1;
+            }
+        })
+    });
+}
+
+fn test_lookup_int(c: &mut Criterion) {
+    lookup_int::<hasher::CityHash>(c, "lookup_int_city");
+    lookup_int::<hasher::Xxh3>(c, "lookup_int_xxh3");
+    lookup_int::<hasher::WyHash>(c, "lookup_int_wyhash");
+    lookup_int::<hasher::IntHash>(c, "lookup_int_int");
+}
+
+fn lookup_values<W, R>(c: &mut Criterion, name: &str, writer: W, reader: R)
+where
+    W: PHashValueSerializer,
+    R: PHashValueDeserializer + Default,
+{
+    let mut keys: Vec<u64> = (0..DEFAULT_LEN as u64).collect();
+    let values: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+    let kvs: Vec<(u64, &[u8])> = keys
+        .iter()
+        .map(|k| (*k, values[*k as usize].as_bytes()))
+        .collect();
+    let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+        CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(0.5f32)),
+        writer,
+    );
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    serializer.write_to(&kvs, &mut buffer).unwrap();
+    let mut deserializer =
+        PerfectHashMapDeserializer::<hasher::CityHash, u64, _, _>::new(CHDReader::new(), reader);
+    deserializer.load_from_buffer(buffer.into_inner()).unwrap();
+    let info = deserializer.info().unwrap();
+    println!(
+        "{} value offsets {}KiB, payload {}KiB",
+        name,
+        info.value_index_size / 1024,
+        (info.value_size - info.value_index_size) / 1024
+    );
+    keys.shuffle(&mut rand::thread_rng());
+
+    let mut idx = 0usize;
+    c.bench_function(name, |b| {
+        b.iter(|| unsafe {
+            for _ in 0..1000 {
+                let k = keys.get_unchecked(idx % keys.len());
+                black_box(deserializer.get(k).unwrap());
+                idx += 1;
+            }
+        })
+    });
+}
+
+/// Space and lookup time of the offset encodings, for short values in a table with
+/// empty slots.
+fn test_value_offsets(c: &mut Criterion) {
+    lookup_values(
+        c,
+        "lookup_offsets_default",
+        DefaultHashValueWriter::new(),
+        DefaultHashValueReader::new(),
+    );
+    lookup_values(
+        c,
+        "lookup_offsets_elias_fano",
+        EliasFanoValueWriter::new(),
+        EliasFanoValueReader::new(),
+    );
+}
+
+const INDEX_LEN: usize = 200_000;
+
+/// Build time, space and lookup time of an index algorithm on `INDEX_LEN` keys.
+fn bench_index<I, R>(c: &mut Criterion, name: &str, new: impl Fn() -> I, reader: R)
+where
+    I: PHashIndexSerializer<String, hasher::CityHash>,
+    R: PHashIndexDeserializer<String, hasher::CityHash> + Default,
+{
+    let mut keys: Vec<String> = (0..INDEX_LEN).map(|i| format!("key-{}", i)).collect();
+    let kvs: Vec<(String, &[u8])> = keys.iter().map(|k| (k.clone(), &b""[..])).collect();
+
+    let mut group = c.benchmark_group("index-build");
+    group.sample_size(10);
+    group.throughput(criterion::Throughput::Elements(INDEX_LEN as u64));
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                new(),
+                DefaultHashValueWriter::new(),
+            );
+            serializer
+                .write_to(&kvs, std::io::Cursor::new(Vec::new()))
+                .unwrap();
+        })
+    });
+    group.finish();
+
+    let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+        new(),
+        DefaultHashValueWriter::new(),
+    );
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    serializer.write_to(&kvs, &mut buffer).unwrap();
+    let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, String, _, _>::new(
+        reader,
+        DefaultHashValueReader::new(),
+    );
+    deserializer.load_from_buffer(buffer.into_inner()).unwrap();
+    let info = deserializer.info().unwrap();
+    println!(
+        "{} index {} bytes, {:.2} bits/key",
+        name,
+        info.index_size,
+        info.index_size as f64 * 8.0 / INDEX_LEN as f64
+    );
+    keys.shuffle(&mut rand::thread_rng());
+
+    let mut idx = 0usize;
+    c.bench_function(&format!("lookup_index_{}", name), |b| {
+        b.iter(|| unsafe {
+            for _ in 0..1000 {
+                let k = keys.get_unchecked(idx % keys.len());
+                black_box(deserializer.get(k).unwrap());
+                idx += 1;
+            }
+        })
+    });
+}
+
+fn test_index(c: &mut Criterion) {
+    bench_index(
+        c,
+        "chd",
+        || CHDGenerator::from_config(CHDGeneratorConfig::default().minimal(true)),
+        CHDReader::new(),
+    );
+    for (name, displacement) in [
+        ("chd_compact", DisplacementEncoding::Compact),
+        ("chd_dictionary", DisplacementEncoding::Dictionary),
+    ] {
+        let config = CHDGeneratorConfig::default()
+            .minimal(true)
+            .displacement(displacement);
+        bench_index(
+            c,
+            name,
+            || CHDGenerator::from_config(config.clone()),
+            CHDReader::new(),
+        );
+    }
+    for (leaf_size, bucket_size) in [(5, 5), (8, 100), (12, 1000)] {
+        let config = recsplit::RecSplitGeneratorConfig::default()
+            .leaf_size(leaf_size)
+            .bucket_size(bucket_size);
+        bench_index(
+            c,
+            &format!("recsplit_{}_{}", leaf_size, bucket_size),
+            || recsplit::RecSplitGenerator::from_config(config.clone()),
+            recsplit::RecSplitReader::new(),
+        );
+    }
+    for gamma in [1.0, 2.0] {
+        let config = bbhash::BBHashGeneratorConfig::default().gamma(gamma);
+        bench_index(
+            c,
+            &format!("bbhash_{}", gamma),
+            || bbhash::BBHashGenerator::from_config(config.clone()),
+            bbhash::BBHashReader::new(),
+        );
+    }
+    for encoding in [
+        pthash::PilotEncoding::Compact,
+        pthash::PilotEncoding::Dictionary,
+        pthash::PilotEncoding::EliasFano,
+    ] {
+        let config = pthash::PTHashGeneratorConfig::default()
+            .minimal(true)
+            .encoding(encoding);
+        bench_index(
+            c,
+            &format!("pthash_{:?}", encoding).to_lowercase(),
+            || pthash::PTHashGenerator::from_config(config.clone()),
+            pthash::PTHashReader::new(),
+        );
+    }
+}
+
 fn test_build(c: &mut Criterion) {
     let mut group = c.benchmark_group("sample-build");
     group.sample_size(10);
@@ -141,6 +351,6 @@ fn test_build(c: &mut Criterion) {
 criterion_group! {
     name=benches;
     config=Criterion::default().sample_size(50);
-    targets = test_lookup, test_lookup_minimal, test_lookup_seq, test_build
+    targets = test_lookup, test_lookup_minimal, test_lookup_seq, test_lookup_int, test_value_offsets, test_index, test_build
 }
 criterion_main!(benches);
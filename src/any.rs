@@ -0,0 +1,185 @@
+use std::borrow::Cow;
+use std::fs::File;
+
+use crate::bbhash::BBHashReader;
+use crate::chd::CHDReader;
+use crate::hasher::{CityHash, IntHash, WyHash, Xxh3};
+use crate::pthash::PTHashReader;
+use crate::recsplit::RecSplitReader;
+use crate::value::{
+    BlockCompressedValueReader, DedupValueReader, DefaultHashValueReader, DictionaryValueReader,
+    EliasFanoValueReader, FixedSizeValueReader,
+};
+use crate::{
+    Data, Error, Hasher, IndexStats, MapInfo, PHashIndexDeserializer, PHashKey,
+    PHashValueDeserializer, PerfectHashMapDeserializer, PerfectHashMapHeader, Result,
+};
+
+/// The key, if stored, and value of a slot.
+pub type Slot<'a> = (Option<&'a [u8]>, Cow<'a, [u8]>);
+
+/// The lookups of a loaded map, with its type parameters erased.
+trait ErasedMap: Send + Sync {
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, [u8]>>;
+    fn verify(&self) -> Result<()>;
+    fn info(&self) -> MapInfo;
+    fn index_stats(&self) -> IndexStats;
+    fn slot_count(&self) -> usize;
+    fn slot(&self, index: u32) -> Option<Slot<'_>>;
+}
+
+impl<H, I, V> ErasedMap for PerfectHashMapDeserializer<H, [u8], I, V>
+where
+    H: Hasher + Send + Sync,
+    I: PHashIndexDeserializer<[u8], H> + Send + Sync,
+    V: PHashValueDeserializer + Send + Sync,
+{
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, [u8]>> {
+        PerfectHashMapDeserializer::get(self, key).map(Into::into)
+    }
+
+    fn verify(&self) -> Result<()> {
+        PerfectHashMapDeserializer::verify(self)
+    }
+
+    fn info(&self) -> MapInfo {
+        // only loaded maps are erased
+        PerfectHashMapDeserializer::info(self).unwrap()
+    }
+
+    fn index_stats(&self) -> IndexStats {
+        PerfectHashMapDeserializer::index_stats(self)
+    }
+
+    fn slot_count(&self) -> usize {
+        PerfectHashMapDeserializer::slot_count(self)
+    }
+
+    fn slot(&self, index: u32) -> Option<Slot<'_>> {
+        let (key, value) = PerfectHashMapDeserializer::slot(self, index)?;
+        Some((key, value.into()))
+    }
+}
+
+/// A map opened without knowing the hasher, index and value encoding it was written
+/// with. They are selected from the ids in the file header.
+///
+/// Keys are looked up by their [`PHashKey`] bytes, so a map of `String` keys is queried
+/// with the UTF-8 bytes and a map of `u64` keys with [`AnyPerfectHashMap::get_key`].
+pub struct AnyPerfectHashMap {
+    inner: Box<dyn ErasedMap>,
+}
+
+impl AnyPerfectHashMap {
+    pub fn load_from_mmap_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let file = File::options().read(true).write(false).open(path)?;
+        let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+        Self::load_from_data(Data::new(mmap))
+    }
+
+    pub fn load_from_buffer<T>(buffer: T) -> Result<Self>
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        Self::load_from_data(Data::new(buffer))
+    }
+
+    pub fn load_from_data(data: Data) -> Result<Self> {
+        let header = PerfectHashMapHeader::parse(&data)?;
+        let ids = (header.index_id, header.value_id);
+        let inner = match header.hasher_id {
+            CityHash::ID => with_index::<CityHash>(ids, data),
+            Xxh3::ID => with_index::<Xxh3>(ids, data),
+            WyHash::ID => with_index::<WyHash>(ids, data),
+            IntHash::ID => with_index::<IntHash>(ids, data),
+            id => Err(Error::UnknownType { kind: "hasher", id }),
+        }?;
+        Ok(Self { inner })
+    }
+
+    /// Values of encodings which decode on access are copied out, others are
+    /// borrowed.
+    pub fn get(&self, key: &[u8]) -> Option<Cow<'_, [u8]>> {
+        self.inner.get(key)
+    }
+
+    pub fn get_key<K: PHashKey + ?Sized>(&self, key: &K) -> Option<Cow<'_, [u8]>> {
+        self.inner.get(key.as_key_bytes().as_ref())
+    }
+
+    /// Recompute the section checksums of the loaded file.
+    pub fn verify(&self) -> Result<()> {
+        self.inner.verify()
+    }
+
+    pub fn info(&self) -> MapInfo {
+        self.inner.info()
+    }
+
+    pub fn index_stats(&self) -> IndexStats {
+        self.inner.index_stats()
+    }
+
+    /// Number of slots, see [`PerfectHashMapDeserializer::slot_count`].
+    pub fn slot_count(&self) -> usize {
+        self.inner.slot_count()
+    }
+
+    /// The key, if stored, and value of slot `index`, see
+    /// [`PerfectHashMapDeserializer::slot`].
+    pub fn slot(&self, index: u32) -> Option<Slot<'_>> {
+        self.inner.slot(index)
+    }
+}
+
+/// `ids` are the index and value ids of the header.
+fn with_index<H: Hasher + Send + Sync + 'static>(
+    ids: (u32, u32),
+    data: Data,
+) -> Result<Box<dyn ErasedMap>> {
+    match ids.0 {
+        id if id == <CHDReader<H> as PHashIndexDeserializer<[u8], H>>::ID => {
+            with_value::<H, CHDReader<H>>(ids.1, data)
+        }
+        id if id == <RecSplitReader<H> as PHashIndexDeserializer<[u8], H>>::ID => {
+            with_value::<H, RecSplitReader<H>>(ids.1, data)
+        }
+        id if id == <BBHashReader<H> as PHashIndexDeserializer<[u8], H>>::ID => {
+            with_value::<H, BBHashReader<H>>(ids.1, data)
+        }
+        id if id == <PTHashReader<H> as PHashIndexDeserializer<[u8], H>>::ID => {
+            with_value::<H, PTHashReader<H>>(ids.1, data)
+        }
+        id => Err(Error::UnknownType { kind: "index", id }),
+    }
+}
+
+fn with_value<H, I>(value_id: u32, data: Data) -> Result<Box<dyn ErasedMap>>
+where
+    H: Hasher + Send + Sync + 'static,
+    I: PHashIndexDeserializer<[u8], H> + Default + Send + Sync + 'static,
+{
+    match value_id {
+        DefaultHashValueReader::ID => load::<H, I, DefaultHashValueReader>(data),
+        FixedSizeValueReader::ID => load::<H, I, FixedSizeValueReader>(data),
+        EliasFanoValueReader::ID => load::<H, I, EliasFanoValueReader>(data),
+        DedupValueReader::ID => load::<H, I, DedupValueReader>(data),
+        BlockCompressedValueReader::ID => load::<H, I, BlockCompressedValueReader>(data),
+        DictionaryValueReader::ID => load::<H, I, DictionaryValueReader>(data),
+        id => Err(Error::UnknownType { kind: "value", id }),
+    }
+}
+
+fn load<H, I, V>(data: Data) -> Result<Box<dyn ErasedMap>>
+where
+    H: Hasher + Send + Sync + 'static,
+    I: PHashIndexDeserializer<[u8], H> + Default + Send + Sync + 'static,
+    V: PHashValueDeserializer + Default + Send + Sync + 'static,
+{
+    let mut map = PerfectHashMapDeserializer::<H, [u8], I, V>::new(I::default(), V::default());
+    map.load_from_data(data)?;
+    Ok(Box::new(map))
+}
@@ -0,0 +1,358 @@
+//! BBHash minimal perfect hashing, after Limasset, Rizk, Chikhi and Peterlongo, "Fast
+//! and scalable minimal perfect hashing for massive key sets", 2017.
+//!
+//! Every level is a bit array of `gamma` times the keys left. Each key hashes to one
+//! bit per level and stays at the first level where no other key left hits the same
+//! bit. The index of a key is the rank of its bit over all levels, so lookups cost a
+//! hash and a rank per level visited, and most keys stop at the first levels.
+//!
+//! A level is built from independent bit updates, which run in parallel.
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rayon::prelude::*;
+
+use crate::hasher::fmix64;
+use crate::{
+    any_as_u8_mut_slice, any_as_u8_slice, Data, Endian, Error, HashIndex, HashIndexSerializeInfo,
+    Hasher, IndexStats, PHashIndexDeserializer, PHashIndexSerializer, PHashKey, Result,
+};
+
+/// Words per rank sample.
+const RANK_BLOCK: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct BBHashGeneratorConfig {
+    /// Bits per key left of every level, at least 1. Larger values take more space
+    /// but build faster and need fewer levels per lookup; 2 is a good trade-off.
+    pub gamma: f32,
+    /// Levels to try before giving up, which only happens for duplicated keys.
+    pub max_levels: u32,
+    /// Seed of the key hashes.
+    pub seed: u64,
+}
+
+impl Default for BBHashGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            gamma: 2.0,
+            max_levels: 64,
+            seed: 0,
+        }
+    }
+}
+
+impl BBHashGeneratorConfig {
+    pub fn gamma(mut self, gamma: f32) -> Self {
+        self.gamma = gamma;
+        self
+    }
+    pub fn max_levels(mut self, max_levels: u32) -> Self {
+        self.max_levels = max_levels;
+        self
+    }
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+#[repr(C, packed)]
+struct Header {
+    levels: u32,
+    flag: u32,
+    key_count: u64,
+    seed: u64,
+    words: u64,
+}
+
+impl Header {
+    fn convert(mut self, endian: Endian) -> Self {
+        self.levels = endian.convert_u32(self.levels);
+        self.flag = endian.convert_u32(self.flag);
+        self.key_count = endian.convert_u64(self.key_count);
+        self.seed = endian.convert_u64(self.seed);
+        self.words = endian.convert_u64(self.words);
+        self
+    }
+}
+
+fn key_hash<K: PHashKey + ?Sized, H: Hasher>(key: &K, seed: u64) -> (u64, u64) {
+    let mut hasher = H::with_seed(seed);
+    hasher.write(key.as_key_bytes().as_ref());
+    let hash = hasher.finish_u128();
+    ((hash >> 64) as u64, hash as u64)
+}
+
+/// Bit of a key within a level of `bits` bits.
+#[inline]
+fn level_bit(hash: (u64, u64), level: u32, bits: u64) -> u64 {
+    let hash = fmix64(hash.1 ^ fmix64(hash.0.wrapping_add(level as u64)));
+    ((hash as u128 * bits as u128) >> 64) as u64
+}
+
+pub struct BBHashGenerator<H> {
+    reader: Option<BBHashReader<H>>,
+    config: BBHashGeneratorConfig,
+}
+
+impl<H> Default for BBHashGenerator<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> BBHashGenerator<H> {
+    pub fn new() -> Self {
+        Self::from_config(BBHashGeneratorConfig::default())
+    }
+    pub fn from_config(config: BBHashGeneratorConfig) -> Self {
+        Self {
+            reader: None,
+            config,
+        }
+    }
+}
+
+impl<K, H> PHashIndexSerializer<K, H> for BBHashGenerator<H>
+where
+    H: Hasher,
+    K: PHashKey + Sync + ?Sized,
+{
+    const ID: u32 = 2;
+    type Deserializer = BBHashReader<H>;
+    fn generate<W>(
+        &mut self,
+        keys: &[&K],
+        writer: &mut W,
+        endian: Endian,
+    ) -> Result<HashIndexSerializeInfo>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        if !(1.0f32..=100.0f32).contains(&self.config.gamma) {
+            return Err(Error::InvalidConfig("gamma must be in 1.0..=100.0"));
+        }
+        if keys.len() > u32::MAX as usize {
+            return Err(Error::InvalidConfig("at most u32::MAX keys are supported"));
+        }
+        let seed = self.config.seed;
+
+        let mut left: Vec<(u64, u64)> = keys
+            .par_iter()
+            .map(|key| key_hash::<K, H>(key, seed))
+            .collect();
+        let mut words: Vec<u64> = Vec::new();
+        let mut level_offsets = vec![0u64];
+        while !left.is_empty() {
+            if level_offsets.len() > self.config.max_levels as usize {
+                return Err(Error::ConstructionFailed);
+            }
+            let level = level_offsets.len() as u32 - 1;
+            let level_words = ((left.len() as f64 * self.config.gamma as f64) as usize)
+                .div_ceil(64)
+                .max(1);
+            let bits = level_words as u64 * 64;
+
+            let hit: Vec<AtomicU64> = (0..level_words).map(|_| AtomicU64::new(0)).collect();
+            let collided: Vec<AtomicU64> = (0..level_words).map(|_| AtomicU64::new(0)).collect();
+            left.par_iter().for_each(|hash| {
+                let bit = level_bit(*hash, level, bits);
+                let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+                if hit[word].fetch_or(mask, Ordering::Relaxed) & mask != 0 {
+                    collided[word].fetch_or(mask, Ordering::Relaxed);
+                }
+            });
+            let collided: Vec<u64> = collided.into_iter().map(AtomicU64::into_inner).collect();
+            words.extend(
+                hit.into_iter()
+                    .zip(&collided)
+                    .map(|(hit, collided)| hit.into_inner() & !collided),
+            );
+            left = left
+                .into_par_iter()
+                .filter(|hash| {
+                    let bit = level_bit(*hash, level, bits);
+                    collided[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+                })
+                .collect();
+            level_offsets.push(words.len() as u64 * 64);
+        }
+
+        let header = Header {
+            levels: level_offsets.len() as u32 - 1,
+            flag: 0,
+            key_count: keys.len() as u64,
+            seed,
+            words: words.len() as u64,
+        };
+        let mut section = Vec::new();
+        unsafe {
+            section.extend_from_slice(any_as_u8_slice(&header.convert(endian)));
+        }
+        let mut rank = 0u64;
+        let mut samples = Vec::with_capacity(words.len() / RANK_BLOCK + 1);
+        for block in words.chunks(RANK_BLOCK) {
+            samples.push(rank);
+            rank += block
+                .iter()
+                .map(|word| word.count_ones() as u64)
+                .sum::<u64>();
+        }
+        for word in level_offsets.iter().chain(&words).chain(&samples) {
+            section.extend_from_slice(&endian.convert_u64(*word).to_ne_bytes());
+        }
+        writer.write_all(&section)?;
+
+        let mut reader = BBHashReader::new();
+        <BBHashReader<H> as PHashIndexDeserializer<K, H>>::load(
+            &mut reader,
+            Data::new(section),
+            endian,
+        )?;
+        self.reader = Some(reader);
+
+        Ok(HashIndexSerializeInfo {
+            max_hash_index: keys.len() as u32,
+        })
+    }
+
+    fn pick(&self, key: &K) -> HashIndex {
+        self.reader.as_ref().unwrap().get_hash_index(key)
+    }
+}
+
+pub struct BBHashReader<H> {
+    header: Header,
+    level_offsets: Data,
+    words: Data,
+    samples: Data,
+    endian: Endian,
+    _pd0: PhantomData<H>,
+}
+
+impl<H> Default for BBHashReader<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> BBHashReader<H> {
+    pub fn new() -> Self {
+        Self {
+            header: Header::default(),
+            level_offsets: Data::default(),
+            words: Data::default(),
+            samples: Data::default(),
+            endian: Endian::NATIVE,
+            _pd0: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn word(&self, index: usize) -> u64 {
+        self.endian.read_u64(&self.words, index)
+    }
+
+    /// Set bits before `bit`.
+    #[inline]
+    fn rank(&self, bit: u64) -> u64 {
+        let word = (bit / 64) as usize;
+        let block = word / RANK_BLOCK;
+        let mut rank = self.endian.read_u64(&self.samples, block);
+        for index in block * RANK_BLOCK..word {
+            rank += self.word(index).count_ones() as u64;
+        }
+        rank + (self.word(word) & ((1 << (bit % 64)) - 1)).count_ones() as u64
+    }
+}
+
+impl<K, H> PHashIndexDeserializer<K, H> for BBHashReader<H>
+where
+    H: Hasher,
+    K: PHashKey + Sync + ?Sized,
+{
+    const ID: u32 = <BBHashGenerator<H> as PHashIndexSerializer<K, H>>::ID;
+    type Serializer = BBHashGenerator<H>;
+    fn load(&mut self, data: Data, endian: Endian) -> Result<()> {
+        let header_len = std::mem::size_of::<Header>();
+        if data.len() < header_len {
+            return Err(Error::Corrupted("index section too small"));
+        }
+        unsafe {
+            any_as_u8_mut_slice(&mut self.header).copy_from_slice(&data[..header_len]);
+        }
+        self.header = self.header.convert(endian);
+        self.endian = endian;
+        let (levels, flag, words) = (self.header.levels, self.header.flag, self.header.words);
+        if flag != 0 {
+            return Err(Error::Corrupted("unknown bbhash flag"));
+        }
+        let offsets_end = (levels as usize + 1) * 8 + header_len;
+        let words_end = (words as usize)
+            .checked_mul(8)
+            .and_then(|len| len.checked_add(offsets_end));
+        let samples_end = words_end
+            .and_then(|end| end.checked_add((words as usize).div_ceil(RANK_BLOCK) * 8))
+            .filter(|end| *end <= data.len())
+            .ok_or(Error::Corrupted("bbhash bits out of bounds"))?;
+        let words_end = words_end.unwrap();
+        self.level_offsets = data.slice(header_len..offsets_end);
+        self.words = data.slice(offsets_end..words_end);
+        self.samples = data.slice(words_end..samples_end);
+        // level offsets must rise within the bits, so lookups stay in bounds
+        for level in 0..=levels as usize {
+            let offset = endian.read_u64(&self.level_offsets, level);
+            let prev = match level {
+                0 => 0,
+                level => endian.read_u64(&self.level_offsets, level - 1) + 1,
+            };
+            if offset < prev || offset > words * 64 || (level == 0 && offset != 0) {
+                return Err(Error::Corrupted("invalid bbhash level offsets"));
+            }
+        }
+        Ok(())
+    }
+    fn max_hash_index(&self) -> HashIndex {
+        self.header.key_count as HashIndex
+    }
+    fn stats(&self) -> IndexStats {
+        let mut histogram = Vec::new();
+        for level in 0..self.header.levels as usize {
+            let beg = self.endian.read_u64(&self.level_offsets, level);
+            let end = self.endian.read_u64(&self.level_offsets, level + 1);
+            let keys: u64 = (beg / 64..end / 64)
+                .map(|word| self.word(word as usize).count_ones() as u64)
+                .sum();
+            histogram.push((level as u64, keys));
+        }
+        IndexStats {
+            max_hash_index: self.header.key_count as HashIndex,
+            fields: vec![
+                ("key_count", self.header.key_count),
+                ("levels", self.header.levels as u64),
+                ("bits", self.header.words * 64),
+                ("seed", self.header.seed),
+            ],
+            histogram,
+        }
+    }
+    fn get_hash_index(&self, key: &K) -> HashIndex {
+        let hash = key_hash::<K, H>(key, self.header.seed);
+        let mut beg = 0;
+        for level in 0..self.header.levels {
+            let end = self
+                .endian
+                .read_u64(&self.level_offsets, level as usize + 1);
+            let bit = beg + level_bit(hash, level, end - beg);
+            if self.word((bit / 64) as usize) & (1 << (bit % 64)) != 0 {
+                return self.rank(bit) as HashIndex;
+            }
+            beg = end;
+        }
+        // only absent keys reach here
+        0
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use phash::bbhash::BBHashReader;
+use phash::chd::{CHDGenerator, CHDGeneratorConfig, CHDReader, DisplacementEncoding};
+use phash::hasher::{CityHash, IntHash, WyHash, Xxh3};
+use phash::pthash::PTHashReader;
+use phash::recsplit::RecSplitReader;
+use phash::value::{
+    BlockCompressedValueReader, DedupValueReader, DefaultHashValueReader, DefaultHashValueWriter,
+    DictionaryValueReader, EliasFanoValueReader, FixedSizeValueReader,
+};
+use phash::{
+    AnyPerfectHashMap, Endian, Hasher, KeyVerification, PHashIndexDeserializer,
+    PHashValueDeserializer, PerfectHashMapSerializer,
+};
+
+type CliResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Parser)]
+#[command(
+    name = "phash",
+    about = "Build, query and inspect perfect hash map files"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build a map from a TSV, CSV or JSON lines file
+    Build(BuildArgs),
+    /// Print the values of keys, raw, one per line
+    Get {
+        file: PathBuf,
+        keys: Vec<String>,
+        #[arg(long, value_enum, default_value_t = KeyType::Str)]
+        key_type: KeyType,
+    },
+    /// Print the header, section sizes and index statistics
+    Stats { file: PathBuf },
+    /// Check the section checksums
+    Verify { file: PathBuf },
+    /// Print every used slot, with its key if the map stores full keys
+    Dump {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = KeyType::Str)]
+        key_type: KeyType,
+        #[arg(long, value_enum, default_value_t = DumpFormat::Tsv)]
+        format: DumpFormat,
+    },
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    input: PathBuf,
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Input format, guessed from the input extension if not given
+    #[arg(long, value_enum)]
+    format: Option<InputFormat>,
+    /// Key column: a 0-based index, a column name with --header, or a JSON field
+    #[arg(long)]
+    key: Option<String>,
+    /// Value column, like --key
+    #[arg(long)]
+    value: Option<String>,
+    /// The first line of TSV/CSV input names the columns
+    #[arg(long)]
+    header: bool,
+    #[arg(long, value_enum, default_value_t = KeyType::Str)]
+    key_type: KeyType,
+    #[arg(long, value_enum, default_value_t = HasherKind::City)]
+    hasher: HasherKind,
+    /// none, full or fingerprint:<bytes>
+    #[arg(long, default_value = "none", value_parser = parse_verification)]
+    verification: KeyVerification,
+    #[arg(long, value_enum)]
+    endian: Option<EndianArg>,
+    #[arg(long)]
+    bucket_element: Option<u32>,
+    #[arg(long)]
+    load_factor: Option<f32>,
+    #[arg(long)]
+    minimal: bool,
+    #[arg(long, value_enum)]
+    displacement: Option<DisplacementArg>,
+    #[arg(long)]
+    retry: Option<u32>,
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long)]
+    seed_retry: Option<u32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputFormat {
+    Tsv,
+    Csv,
+    Jsonl,
+}
+
+/// How command line and input keys are turned into key bytes.
+#[derive(Clone, Copy, ValueEnum)]
+enum KeyType {
+    /// UTF-8 bytes, the encoding of `str` and `String` keys
+    Str,
+    /// Little endian, the encoding of `u64` keys
+    U64,
+    /// Little endian, the encoding of `i64` keys
+    I64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HasherKind {
+    City,
+    Xxh3,
+    Wyhash,
+    Int,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EndianArg {
+    Little,
+    Big,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DisplacementArg {
+    Plain,
+    Compact,
+    Dictionary,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DumpFormat {
+    Tsv,
+    Jsonl,
+}
+
+fn parse_verification(s: &str) -> Result<KeyVerification, String> {
+    match s {
+        "none" => Ok(KeyVerification::None),
+        "full" => Ok(KeyVerification::FullKey),
+        _ => s
+            .strip_prefix("fingerprint:")
+            .and_then(|width| width.parse().ok())
+            .map(KeyVerification::Fingerprint)
+            .ok_or_else(|| format!("expected none, full or fingerprint:<bytes>, got {}", s)),
+    }
+}
+
+impl KeyType {
+    fn encode(self, key: &str) -> CliResult<Vec<u8>> {
+        Ok(match self {
+            KeyType::Str => key.as_bytes().to_vec(),
+            KeyType::U64 => key.trim().parse::<u64>()?.to_le_bytes().to_vec(),
+            KeyType::I64 => key.trim().parse::<i64>()?.to_le_bytes().to_vec(),
+        })
+    }
+
+    fn decode(self, key: &[u8]) -> String {
+        match (self, <[u8; 8]>::try_from(key)) {
+            (KeyType::U64, Ok(bytes)) => u64::from_le_bytes(bytes).to_string(),
+            (KeyType::I64, Ok(bytes)) => i64::from_le_bytes(bytes).to_string(),
+            _ => String::from_utf8_lossy(key).into_owned(),
+        }
+    }
+}
+
+/// Resolve a column given by index or, with a header line, by name.
+fn column_index(column: &str, headers: Option<&csv::StringRecord>) -> CliResult<usize> {
+    if let Ok(index) = column.parse() {
+        return Ok(index);
+    }
+    headers
+        .and_then(|headers| headers.iter().position(|name| name == column))
+        .ok_or_else(|| format!("unknown column {}", column).into())
+}
+
+fn read_delimited(args: &BuildArgs, delimiter: u8) -> CliResult<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .quoting(delimiter != b'\t')
+        .has_headers(args.header)
+        .flexible(true)
+        .from_path(&args.input)?;
+    let headers = if args.header {
+        Some(reader.headers()?.clone())
+    } else {
+        None
+    };
+    let key = column_index(args.key.as_deref().unwrap_or("0"), headers.as_ref())?;
+    let value = column_index(args.value.as_deref().unwrap_or("1"), headers.as_ref())?;
+
+    let mut kvs = Vec::new();
+    for (line, record) in reader.byte_records().enumerate() {
+        let record = record?;
+        let (Some(k), Some(v)) = (record.get(key), record.get(value)) else {
+            return Err(format!("record {}: missing key or value column", line + 1).into());
+        };
+        let k = std::str::from_utf8(k)
+            .map_err(|_| format!("record {}: key is not valid UTF-8", line + 1))?;
+        let k = args.key_type.encode(k)?;
+        kvs.push((k, v.to_vec()));
+    }
+    Ok(kvs)
+}
+
+fn read_jsonl(args: &BuildArgs) -> CliResult<Vec<(Vec<u8>, Vec<u8>)>> {
+    let key = args.key.as_deref().unwrap_or("key");
+    let value = args.value.as_deref().unwrap_or("value");
+    let text = |v: &serde_json::Value| match v {
+        serde_json::Value::String(s) => s.clone(),
+        v => v.to_string(),
+    };
+
+    let mut kvs = Vec::new();
+    let input = std::io::BufReader::new(std::fs::File::open(&args.input)?);
+    for (line, text_line) in input.lines().enumerate() {
+        let text_line = text_line?;
+        if text_line.trim().is_empty() {
+            continue;
+        }
+        let object: serde_json::Value = serde_json::from_str(&text_line)?;
+        let (Some(k), Some(v)) = (object.get(key), object.get(value)) else {
+            return Err(format!("line {}: missing {} or {}", line + 1, key, value).into());
+        };
+        kvs.push((args.key_type.encode(&text(k))?, text(v).into_bytes()));
+    }
+    Ok(kvs)
+}
+
+fn build(args: &BuildArgs) -> CliResult<()> {
+    let format = match args.format {
+        Some(format) => format,
+        None => match args.input.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => InputFormat::Csv,
+            Some("jsonl" | "json" | "ndjson") => InputFormat::Jsonl,
+            _ => InputFormat::Tsv,
+        },
+    };
+    let kvs = match format {
+        InputFormat::Tsv => read_delimited(args, b'\t')?,
+        InputFormat::Csv => read_delimited(args, b',')?,
+        InputFormat::Jsonl => read_jsonl(args)?,
+    };
+    let mut seen = HashSet::new();
+    for (key, _) in &kvs {
+        if !seen.insert(key.as_slice()) {
+            return Err(format!("duplicate key {}", args.key_type.decode(key)).into());
+        }
+    }
+
+    let mut config = CHDGeneratorConfig::default().minimal(args.minimal);
+    if let Some(v) = args.bucket_element {
+        config = config.bucket_element(v);
+    }
+    if let Some(v) = args.load_factor {
+        config = config.load_factor(v);
+    }
+    if let Some(v) = args.displacement {
+        config = config.displacement(match v {
+            DisplacementArg::Plain => DisplacementEncoding::Plain,
+            DisplacementArg::Compact => DisplacementEncoding::Compact,
+            DisplacementArg::Dictionary => DisplacementEncoding::Dictionary,
+        });
+    }
+    if let Some(v) = args.retry {
+        config = config.retry(v);
+    }
+    if let Some(v) = args.seed {
+        config = config.seed(v);
+    }
+    if let Some(v) = args.seed_retry {
+        config = config.seed_retry(v);
+    }
+    let endian = match args.endian {
+        Some(EndianArg::Little) => Endian::Little,
+        Some(EndianArg::Big) => Endian::Big,
+        None => Endian::NATIVE,
+    };
+
+    match args.hasher {
+        HasherKind::City => build_with::<CityHash>(args, config, endian, kvs),
+        HasherKind::Xxh3 => build_with::<Xxh3>(args, config, endian, kvs),
+        HasherKind::Wyhash => build_with::<WyHash>(args, config, endian, kvs),
+        HasherKind::Int => build_with::<IntHash>(args, config, endian, kvs),
+    }
+}
+
+fn build_with<H: Hasher>(
+    args: &BuildArgs,
+    config: CHDGeneratorConfig,
+    endian: Endian,
+    kvs: Vec<(Vec<u8>, Vec<u8>)>,
+) -> CliResult<()> {
+    let (keys, values): (Vec<_>, Vec<_>) = kvs.into_iter().unzip();
+    let kvs: Vec<(Vec<u8>, &[u8])> = keys
+        .into_iter()
+        .zip(values.iter().map(|v| v.as_slice()))
+        .collect();
+    let mut serializer = PerfectHashMapSerializer::<H, _, _, _>::new(
+        CHDGenerator::from_config(config),
+        DefaultHashValueWriter::new(),
+    )
+    .verification(args.verification)
+    .endian(endian);
+    serializer.write_to_file(&kvs, &args.output)?;
+    println!("wrote {} keys to {}", kvs.len(), args.output.display());
+    Ok(())
+}
+
+fn get(file: &Path, keys: &[String], key_type: KeyType) -> CliResult<bool> {
+    let map = AnyPerfectHashMap::load_from_mmap_file(file)?;
+    let mut stdout = std::io::stdout().lock();
+    let mut found_all = true;
+    for key in keys {
+        match map.get(&key_type.encode(key)?) {
+            Some(value) => {
+                stdout.write_all(&value)?;
+                stdout.write_all(b"\n")?;
+            }
+            None => {
+                eprintln!("{}: not found", key);
+                found_all = false;
+            }
+        }
+    }
+    Ok(found_all)
+}
+
+fn hasher_name(id: u32) -> &'static str {
+    match id {
+        CityHash::ID => "city",
+        Xxh3::ID => "xxh3",
+        WyHash::ID => "wyhash",
+        IntHash::ID => "int",
+        _ => "unknown",
+    }
+}
+
+fn index_name(id: u32) -> &'static str {
+    match id {
+        id if id == <CHDReader<CityHash> as PHashIndexDeserializer<[u8], CityHash>>::ID => "chd",
+        id if id == <RecSplitReader<CityHash> as PHashIndexDeserializer<[u8], CityHash>>::ID => {
+            "recsplit"
+        }
+        id if id == <BBHashReader<CityHash> as PHashIndexDeserializer<[u8], CityHash>>::ID => {
+            "bbhash"
+        }
+        id if id == <PTHashReader<CityHash> as PHashIndexDeserializer<[u8], CityHash>>::ID => {
+            "pthash"
+        }
+        _ => "unknown",
+    }
+}
+
+fn value_name(id: u32) -> &'static str {
+    match id {
+        DefaultHashValueReader::ID => "default",
+        FixedSizeValueReader::ID => "fixed",
+        EliasFanoValueReader::ID => "elias-fano",
+        DedupValueReader::ID => "dedup",
+        BlockCompressedValueReader::ID => "lz4-blocks",
+        DictionaryValueReader::ID => "lz4-dictionary",
+        _ => "unknown",
+    }
+}
+
+fn stats(file: &Path) -> CliResult<()> {
+    let map = AnyPerfectHashMap::load_from_mmap_file(file)?;
+    let info = map.info();
+    let index = map.index_stats();
+
+    println!("version            {}", info.version);
+    println!("endian             {:?}", info.endian);
+    println!(
+        "hasher             {} ({})",
+        hasher_name(info.hasher_id),
+        info.hasher_id
+    );
+    println!(
+        "index              {} ({})",
+        index_name(info.index_id),
+        info.index_id
+    );
+    println!(
+        "values             {} ({})",
+        value_name(info.value_id),
+        info.value_id
+    );
+    println!("verification       {:?}", info.verification);
+    println!("keys               {}", info.key_count);
+    println!(
+        "load factor        {:.4}",
+        info.key_count as f64 / index.max_hash_index.max(1) as f64
+    );
+    println!("slots              {}", index.max_hash_index);
+    for (name, value) in &index.fields {
+        println!("{:<18} {}", name, value);
+    }
+
+    println!();
+    println!("file size          {}", info.file_size);
+    println!("header             {}", info.header_size);
+    println!("index section      {}", info.index_size);
+    println!("value index        {}", info.value_index_size);
+    println!(
+        "value payload      {}",
+        info.value_size - info.value_index_size
+    );
+    println!("verification       {}", info.verify_size);
+
+    if !index.histogram.is_empty() {
+        let label = match index_name(info.index_id) {
+            "chd" => "displacement",
+            "recsplit" => "bucket size",
+            "bbhash" => "level",
+            "pthash" => "pilot bits",
+            _ => "parameter",
+        };
+        println!();
+        println!("{} histogram", label);
+        // power of two ranges, the exact values are spread too wide
+        let mut ranges = std::collections::BTreeMap::<u32, u64>::new();
+        for (value, count) in &index.histogram {
+            *ranges.entry(u64::BITS - value.leading_zeros()).or_default() += count;
+        }
+        for (bits, count) in ranges {
+            let (lo, hi) = match bits {
+                0 => (0, 0),
+                bits => (1u64 << (bits - 1), (1u64 << bits) - 1),
+            };
+            println!("{:>10}..={:<10} {}", lo, hi, count);
+        }
+    }
+    Ok(())
+}
+
+fn verify(file: &Path) -> CliResult<()> {
+    let map = AnyPerfectHashMap::load_from_mmap_file(file)?;
+    map.verify()?;
+    println!("ok");
+    Ok(())
+}
+
+fn escape(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn dump(file: &Path, key_type: KeyType, format: DumpFormat) -> CliResult<()> {
+    let map = AnyPerfectHashMap::load_from_mmap_file(file)?;
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    for index in 0..map.slot_count() as u32 {
+        let (key, value) = map.slot(index).unwrap();
+        // unused slots hold an empty value and no key
+        if value.is_empty() && key.is_none() {
+            continue;
+        }
+        let key = key.map(|key| key_type.decode(key));
+        match format {
+            DumpFormat::Tsv => {
+                let key = key.map(|key| escape(key.as_bytes()));
+                let key = key.unwrap_or_else(|| format!("#{}", index));
+                writeln!(stdout, "{}\t{}", key, escape(&value))?;
+            }
+            DumpFormat::Jsonl => {
+                let line = serde_json::json!({
+                    "slot": index,
+                    "key": key,
+                    "value": String::from_utf8_lossy(&value),
+                });
+                writeln!(stdout, "{}", line)?;
+            }
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match &cli.command {
+        Command::Build(args) => build(args).map(|_| true),
+        Command::Get {
+            file,
+            keys,
+            key_type,
+        } => get(file, keys, *key_type),
+        Command::Stats { file } => stats(file).map(|_| true),
+        Command::Verify { file } => verify(file).map(|_| true),
+        Command::Dump {
+            file,
+            key_type,
+            format,
+        } => dump(file, *key_type, *format).map(|_| true),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,16 +1,40 @@
-use std::hash::Hash;
 use std::marker::PhantomData;
 use std::num::Wrapping;
 
-use crate::{any_array_as_u8_slice, any_as_u8_slice, HashIndexSerializeInfo, Hasher};
-use crate::{any_as_u8_mut_slice, HashIndex, PHashIndexDeserializer, PHashIndexSerializer};
+use crate::compact::{self, Compact, Dictionary};
+use crate::{
+    any_array_as_u8_slice, any_as_u8_slice, Data, Endian, Error, HashIndexSerializeInfo, Hasher,
+    IndexStats, Result,
+};
+use crate::{
+    any_as_u8_mut_slice, HashIndex, PHashIndexDeserializer, PHashIndexSerializer, PHashKey,
+};
+
+/// How the displacement of every bucket is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplacementEncoding {
+    /// A `u32` per bucket.
+    #[default]
+    Plain,
+    /// Every displacement in the bits of the largest one.
+    Compact,
+    /// The distinct displacements once, and per bucket an index into them, as in the
+    /// compress-dictionary scheme of the CHD paper.
+    Dictionary,
+}
 
 #[derive(Debug, Clone)]
 pub struct CHDGeneratorConfig {
     pub bucket_element: u32,
     pub load_factor: f32,
     pub minimal: bool,
+    pub displacement: DisplacementEncoding,
+    /// How many times the table grows after every seed of a size failed.
     pub retry: u32,
+    /// First seed to try.
+    pub seed: u64,
+    /// How many seeds to try before growing the table.
+    pub seed_retry: u32,
 }
 
 impl Default for CHDGeneratorConfig {
@@ -19,7 +43,10 @@ impl Default for CHDGeneratorConfig {
             bucket_element: 5,
             load_factor: 0.99f32,
             minimal: false,
+            displacement: DisplacementEncoding::default(),
             retry: 3,
+            seed: 0,
+            seed_retry: 8,
         }
     }
 }
@@ -37,15 +64,26 @@ impl CHDGeneratorConfig {
         self.minimal = minimal;
         self
     }
+    pub fn displacement(mut self, displacement: DisplacementEncoding) -> Self {
+        self.displacement = displacement;
+        self
+    }
     pub fn retry(mut self, retry: u32) -> Self {
         self.retry = retry;
         self
     }
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    pub fn seed_retry(mut self, seed_retry: u32) -> Self {
+        self.seed_retry = seed_retry;
+        self
+    }
 }
 
 pub struct CHDGenerator<H> {
     reader: Option<CHDReader<H>>,
-    mapping: Vec<u8>,
     config: CHDGeneratorConfig,
 }
 
@@ -55,25 +93,36 @@ struct Bucket {
     hashes: Vec<(u32, u32)>,
 }
 
+impl<H> Default for CHDGenerator<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<H> CHDGenerator<H> {
     pub fn new() -> Self {
         Self {
             reader: None,
-            mapping: Vec::new(),
             config: CHDGeneratorConfig::default(),
         }
     }
     pub fn from_config(config: CHDGeneratorConfig) -> Self {
         Self {
             reader: None,
-            mapping: Vec::new(),
             config,
         }
     }
 }
 
-#[derive(Default)]
-#[repr(packed)]
+// The header is followed by a `u64` seed and the displacements of the buckets, a
+// `u32` each unless a flag selects another encoding.
+/// The displacements are written by [`compact::write`].
+const FLAG_COMPACT: u32 = 1;
+/// The displacements are written by [`compact::write_dictionary`].
+const FLAG_DICTIONARY: u32 = 2;
+
+#[derive(Default, Clone, Copy)]
+#[repr(C, packed)]
 #[allow(unused)]
 struct Header {
     flag: u32,
@@ -81,23 +130,41 @@ struct Header {
     bucket_size: u32,
 }
 
+impl Header {
+    fn convert(mut self, endian: Endian) -> Self {
+        self.flag = endian.convert_u32(self.flag);
+        self.table_size = endian.convert_u32(self.table_size);
+        self.bucket_size = endian.convert_u32(self.bucket_size);
+        self
+    }
+}
+
 struct KeyHash {
     h: u32,
     h0: u32,
     h1: u32,
 }
 
-fn key_hash<K: Hash, H: Hasher>(k: &K, bucket_size: u32, table_size: u32) -> KeyHash {
-    let mut hasher = H::default();
-    k.hash(&mut hasher);
+fn key_hash<K: PHashKey + ?Sized, H: Hasher>(
+    k: &K,
+    seed: u64,
+    bucket_size: u32,
+    table_size: u32,
+) -> KeyHash {
+    let mut hasher = H::with_seed(seed);
+    hasher.write(k.as_key_bytes().as_ref());
     let hash = hasher.finish_u128();
 
     let h = (hash >> 64) as u32 % bucket_size;
-    let h0 = (((hash >> 32) as u32) % table_size) as u32;
-    let h1 = (((hash & 0xFFFFFFFF) as u32) % table_size) as u32;
+    let h0 = ((hash >> 32) as u32) % table_size;
+    let h1 = ((hash & 0xFFFFFFFF) as u32) % table_size;
     KeyHash { h, h0, h1 }
 }
 
+fn next_seed(seed: u64) -> u64 {
+    seed.wrapping_add(0x9E3779B97F4A7C15)
+}
+
 #[inline]
 fn displace(h0: u32, h1: u32, d0: u32, d1: u32) -> u32 {
     (Wrapping(h0) + (Wrapping(h1) * Wrapping(d1)) + Wrapping(d0)).0
@@ -107,19 +174,27 @@ impl<H> CHDGenerator<H>
 where
     H: Hasher,
 {
-    fn try_generate<'a,K>(&mut self, keys: &Vec<&'a K>, table_size: u32, bucket_size: u32)  -> Option<(Header, Vec<u32>)>
-    where K: Hash {
+    fn try_generate<K>(
+        &mut self,
+        keys: &[&K],
+        seed: u64,
+        table_size: u32,
+        bucket_size: u32,
+    ) -> Option<(Header, Vec<u32>)>
+    where
+        K: PHashKey + ?Sized,
+    {
         let mut buckets = Vec::<Bucket>::new();
         buckets.resize(bucket_size as usize, Bucket::default());
         for key in keys {
-            let key_hash = key_hash::<K, H>(key, bucket_size, table_size);
+            let key_hash = key_hash::<K, H>(key, seed, bucket_size, table_size);
             buckets[key_hash.h as usize].index = key_hash.h;
             buckets[key_hash.h as usize]
                 .hashes
                 .push((key_hash.h0, key_hash.h1));
         }
 
-        buckets.sort_by(|a, b| b.hashes.len().cmp(&a.hashes.len()));
+        buckets.sort_by_key(|b| std::cmp::Reverse(b.hashes.len()));
 
         let mut used = bitvec::vec::BitVec::<usize>::new();
         used.resize(table_size as usize, false);
@@ -127,14 +202,13 @@ where
         let max_len_of_hashes = buckets[0].hashes.len();
         let mut pushed = Vec::with_capacity(max_len_of_hashes);
 
-        let max_hash_func = u32::min(table_size * table_size, 1 << 24);
+        let max_hash_func = (table_size as u64 * table_size as u64).min(1 << 24) as u32;
 
-        let mut result = Vec::new();
-        result.resize(table_size as usize, 0 as u32);
+        let mut result = vec![0u32; table_size as usize];
 
         // displace all
         for bucket in &mut buckets {
-            if bucket.hashes.len() == 0 {
+            if bucket.hashes.is_empty() {
                 continue;
             }
             let mut hash_func = 0;
@@ -143,7 +217,7 @@ where
             let mut ok = false;
             while !ok {
                 ok = true;
-                pushed.resize(0, 0);
+                pushed.clear();
                 for (h0, h1) in &bucket.hashes {
                     let h0 = *h0;
                     let h1 = *h1;
@@ -173,7 +247,7 @@ where
                         return None;
                     }
                 } else {
-                    result[bucket.index as usize] = hash_func as u32;
+                    result[bucket.index as usize] = hash_func;
                 }
             }
         }
@@ -190,57 +264,91 @@ where
 impl<K, H> PHashIndexSerializer<K, H> for CHDGenerator<H>
 where
     H: Hasher,
-    K: Hash,
+    K: PHashKey + ?Sized,
 {
+    const ID: u32 = 0;
     type Deserializer = CHDReader<H>;
-    fn generate<'a, W>(
+    fn generate<W>(
         &mut self,
-        keys: &Vec<&'a K>,
+        keys: &[&K],
         writer: &mut W,
-    ) -> Option<HashIndexSerializeInfo>
+        endian: Endian,
+    ) -> Result<HashIndexSerializeInfo>
     where
         W: std::io::Write + std::io::Seek,
     {
-        assert!(self.config.bucket_element >= 1 && self.config.bucket_element <= 1000);
-        assert!(self.config.load_factor <= 1.0f32 && self.config.load_factor >= 0.05f32);
+        if !(1..=1000).contains(&self.config.bucket_element) {
+            return Err(Error::InvalidConfig("bucket_element must be in 1..=1000"));
+        }
+        if !(0.05f32..=1.0f32).contains(&self.config.load_factor) {
+            return Err(Error::InvalidConfig("load_factor must be in 0.05..=1.0"));
+        }
+        if keys.is_empty() {
+            return Err(Error::InvalidConfig("chd needs at least one key"));
+        }
 
         let mut table_size = (keys.len() as f32 / self.config.load_factor) as u32;
         if self.config.minimal {
             table_size = keys.len() as u32;
         }
 
-        let bucket_size = (keys.len() as u32 + self.config.bucket_element - 1) / self.config.bucket_element;
+        let bucket_size = (keys.len() as u32).div_ceil(self.config.bucket_element);
 
+        if self.config.seed_retry == 0 {
+            return Err(Error::InvalidConfig("seed_retry must be at least 1"));
+        }
 
-        let (header, result) = loop {
-            if self.config.retry == 0 {
-                return None;
-            }
-            if let Some(v) =  self.try_generate(&keys, table_size, bucket_size) {
-                break v
+        let mut seed = self.config.seed;
+        let mut found = None;
+        'search: for _ in 0..self.config.retry {
+            for _ in 0..self.config.seed_retry {
+                if let Some(v) = self.try_generate(keys, seed, table_size, bucket_size) {
+                    found = Some(v);
+                    break 'search;
+                }
+                seed = next_seed(seed);
             }
             table_size += 1;
-            self.config.retry -= 1;
+        }
+        let (mut header, result) = found.ok_or(Error::ConstructionFailed)?;
+        header.flag |= match self.config.displacement {
+            DisplacementEncoding::Plain => 0,
+            DisplacementEncoding::Compact => FLAG_COMPACT,
+            DisplacementEncoding::Dictionary => FLAG_DICTIONARY,
         };
 
+        let mut section = Vec::new();
         unsafe {
-            writer.write(any_as_u8_slice(&header)).unwrap();
-            writer
-                .write(any_array_as_u8_slice(result.as_slice()))
-                .unwrap();
+            section.extend_from_slice(any_as_u8_slice(&header.convert(endian)));
         }
-
-        self.mapping.resize(result.as_slice().len() * 4, 0);
-        unsafe {
-            self.mapping
-                .copy_from_slice(any_array_as_u8_slice(result.as_slice()));
+        section.extend_from_slice(&endian.convert_u64(seed).to_ne_bytes());
+        // only the first bucket_size displacements are used
+        let used = &result[..header.bucket_size as usize];
+        let wide = || used.iter().map(|v| *v as u64).collect::<Vec<u64>>();
+        match self.config.displacement {
+            DisplacementEncoding::Plain => {
+                let displacements: Vec<u32> = used.iter().map(|v| endian.convert_u32(*v)).collect();
+                unsafe {
+                    section.extend_from_slice(any_array_as_u8_slice(displacements.as_slice()));
+                }
+            }
+            DisplacementEncoding::Compact => compact::write(&wide(), &mut section, endian)?,
+            DisplacementEncoding::Dictionary => {
+                compact::write_dictionary(&wide(), &mut section, endian)?
+            }
         }
-
-        let reader = CHDReader::with(self.mapping.as_ptr() as *const u32, header);
+        writer.write_all(&section)?;
+
+        let mut reader = CHDReader::new();
+        <CHDReader<H> as PHashIndexDeserializer<K, H>>::load(
+            &mut reader,
+            Data::new(section),
+            endian,
+        )?;
         self.reader = Some(reader);
 
-        Some(HashIndexSerializeInfo {
-            max_hash_index: table_size,
+        Ok(HashIndexSerializeInfo {
+            max_hash_index: header.table_size,
         })
     }
 
@@ -249,26 +357,44 @@ where
     }
 }
 
+/// Displacements as stored by each [`DisplacementEncoding`].
+enum Displacements {
+    Plain(Data),
+    Compact(Compact),
+    Dictionary(Dictionary),
+}
+
 pub struct CHDReader<H> {
     header: Header,
-    ptr: *const u32,
+    seed: u64,
+    displacements: Displacements,
+    endian: Endian,
     _pd0: PhantomData<H>,
 }
 
+impl<H> Default for CHDReader<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<H> CHDReader<H> {
     pub fn new() -> Self {
         Self {
-            ptr: std::ptr::null(),
+            displacements: Displacements::Plain(Data::default()),
             header: Header::default(),
-            _pd0: PhantomData::default(),
+            seed: 0,
+            endian: Endian::NATIVE,
+            _pd0: PhantomData,
         }
     }
 
-    fn with(ptr: *const u32, header: Header) -> Self {
-        Self {
-            ptr,
-            header,
-            _pd0: PhantomData::default(),
+    #[inline]
+    fn displacement(&self, bucket: usize) -> u32 {
+        match &self.displacements {
+            Displacements::Plain(displacements) => self.endian.read_u32(displacements, bucket),
+            Displacements::Compact(displacements) => displacements.get(bucket) as u32,
+            Displacements::Dictionary(displacements) => displacements.get(bucket) as u32,
         }
     }
 }
@@ -276,25 +402,86 @@ impl<H> CHDReader<H> {
 impl<K, H> PHashIndexDeserializer<K, H> for CHDReader<H>
 where
     H: Hasher,
-    K: Hash,
+    K: PHashKey + ?Sized,
 {
+    const ID: u32 = <CHDGenerator<H> as PHashIndexSerializer<K, H>>::ID;
     type Serializer = CHDGenerator<H>;
-    fn load<'a>(&'a mut self, ptr: &'a [u8]) -> Option<()> {
+    fn load(&mut self, data: Data, endian: Endian) -> Result<()> {
+        let header_len = std::mem::size_of::<Header>();
+        let seed_len = std::mem::size_of::<u64>();
+        if data.len() < header_len + seed_len {
+            return Err(Error::Corrupted("index section too small"));
+        }
         unsafe {
-            let desc = any_as_u8_mut_slice(&mut self.header);
-            std::ptr::copy(ptr.as_ptr(), desc.as_mut_ptr(), desc.len());
-            self.ptr = ptr.as_ptr().add(std::mem::size_of::<Header>()) as *const u32;
+            any_as_u8_mut_slice(&mut self.header).copy_from_slice(&data[..header_len]);
+        }
+        self.header = self.header.convert(endian);
+        self.endian = endian;
+        let flag = self.header.flag;
+        if flag & !(FLAG_COMPACT | FLAG_DICTIONARY) != 0
+            || flag & FLAG_COMPACT != 0 && flag & FLAG_DICTIONARY != 0
+        {
+            return Err(Error::Corrupted("unknown chd flag"));
+        }
+        let seed = u64::from_ne_bytes(data[header_len..header_len + seed_len].try_into().unwrap());
+        self.seed = endian.convert_u64(seed);
+        let table_size = self.header.table_size;
+        let bucket_size = self.header.bucket_size;
+        if table_size == 0 || bucket_size == 0 || bucket_size > table_size {
+            return Err(Error::Corrupted("invalid chd table size"));
+        }
+        let displacements = data.slice(header_len + seed_len..data.len());
+        let len = if flag & FLAG_COMPACT != 0 {
+            let (compact, _) = Compact::load(&displacements, endian)?;
+            let len = compact.len();
+            self.displacements = Displacements::Compact(compact);
+            len
+        } else if flag & FLAG_DICTIONARY != 0 {
+            let (dictionary, _) = Dictionary::load(&displacements, endian)?;
+            let len = dictionary.len();
+            self.displacements = Displacements::Dictionary(dictionary);
+            len
+        } else {
+            self.displacements = Displacements::Plain(displacements.clone());
+            displacements.len() / std::mem::size_of::<u32>()
+        };
+        if len < bucket_size as usize {
+            return Err(Error::Corrupted("chd displacements out of bounds"));
+        }
+        Ok(())
+    }
+    fn max_hash_index(&self) -> HashIndex {
+        self.header.table_size
+    }
+    fn stats(&self) -> IndexStats {
+        let mut histogram = std::collections::BTreeMap::<u64, u64>::new();
+        for bucket in 0..self.header.bucket_size as usize {
+            let hash_func = self.displacement(bucket);
+            *histogram.entry(hash_func as u64).or_default() += 1;
+        }
+        IndexStats {
+            max_hash_index: self.header.table_size,
+            fields: vec![
+                ("table_size", self.header.table_size as u64),
+                ("bucket_size", self.header.bucket_size as u64),
+                ("seed", self.seed),
+            ],
+            histogram: histogram.into_iter().collect(),
         }
-        Some(())
     }
     fn get_hash_index(&self, key: &K) -> HashIndex {
-        let key_hash = key_hash::<K, H>(key, self.header.bucket_size, self.header.table_size);
+        let key_hash = key_hash::<K, H>(
+            key,
+            self.seed,
+            self.header.bucket_size,
+            self.header.table_size,
+        );
 
         let h = key_hash.h;
         let h0 = key_hash.h0;
         let h1 = key_hash.h1;
 
-        let hash_func = unsafe { *self.ptr.add(h as usize) } as u32;
+        let hash_func = self.displacement(h as usize);
 
         let table_size = self.header.table_size;
 
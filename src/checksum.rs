@@ -0,0 +1,53 @@
+use std::io::{Seek, SeekFrom, Write};
+
+/// Passes writes through while computing the crc32 of everything written since the
+/// last [`ChecksumWriter::take`].
+///
+/// Only `SeekFrom::Current(0)` is supported, as moving the cursor would leave holes
+/// or overwritten bytes the checksum does not reflect.
+pub(crate) struct ChecksumWriter<'a, W> {
+    inner: &'a mut W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<'a, W> ChecksumWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Return the checksum of the bytes written so far and start a new one.
+    pub(crate) fn take(&mut self) -> u32 {
+        std::mem::take(&mut self.hasher).finalize()
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for ChecksumWriter<'_, W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => self.inner.stream_position(),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "seek inside a checksummed section",
+            )),
+        }
+    }
+}
+
+pub(crate) fn checksum(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
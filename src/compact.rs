@@ -0,0 +1,140 @@
+//! Integer sequences stored in the bits of their largest value.
+//!
+//! A [`Dictionary`] stores the distinct values once and every value as a compact
+//! index into them, which is smaller when few distinct values repeat a lot.
+
+use crate::{Data, Endian, Error, Result};
+
+/// Encode `values` as `u64` length and width in `endian`, followed by the words.
+pub(crate) fn write<W>(values: &[u64], writer: &mut W, endian: Endian) -> Result<()>
+where
+    W: std::io::Write,
+{
+    let width = values
+        .iter()
+        .map(|value| 64 - value.leading_zeros() as u64)
+        .max()
+        .unwrap_or(0);
+    let mut words = vec![0u64; (values.len() as u64 * width).div_ceil(64) as usize];
+    if width > 0 {
+        for (i, value) in values.iter().enumerate() {
+            let pos = i as u64 * width;
+            let (word, shift) = ((pos / 64) as usize, pos % 64);
+            words[word] |= value << shift;
+            if shift + width > 64 {
+                words[word + 1] |= value >> (64 - shift);
+            }
+        }
+    }
+    for word in [values.len() as u64, width].iter().chain(&words) {
+        writer.write_all(&endian.convert_u64(*word).to_ne_bytes())?;
+    }
+    Ok(())
+}
+
+/// Encode `values` as the sorted distinct values followed by their indexes, both
+/// written by [`write`].
+pub(crate) fn write_dictionary<W>(values: &[u64], writer: &mut W, endian: Endian) -> Result<()>
+where
+    W: std::io::Write,
+{
+    let mut dictionary = values.to_vec();
+    dictionary.sort_unstable();
+    dictionary.dedup();
+    let ids: Vec<u64> = values
+        .iter()
+        .map(|value| dictionary.binary_search(value).unwrap() as u64)
+        .collect();
+    write(&dictionary, writer, endian)?;
+    write(&ids, writer, endian)
+}
+
+/// A sequence written by [`write`], read in place.
+#[derive(Default)]
+pub(crate) struct Compact {
+    len: u64,
+    width: u64,
+    words: Data,
+    endian: Endian,
+}
+
+impl Compact {
+    /// Load the sequence at the start of `data`, returning it and the length it takes.
+    pub(crate) fn load(data: &Data, endian: Endian) -> Result<(Self, usize)> {
+        if data.len() < 16 {
+            return Err(Error::Corrupted("compact header too small"));
+        }
+        let (len, width) = (endian.read_u64(data, 0), endian.read_u64(data, 1));
+        if width > 64 {
+            return Err(Error::Corrupted("invalid compact width"));
+        }
+        let end = len
+            .checked_mul(width)
+            .map(|bits| bits.div_ceil(64) * 8)
+            .and_then(|bytes| (bytes as usize).checked_add(16))
+            .filter(|end| *end <= data.len())
+            .ok_or(Error::Corrupted("compact words out of bounds"))?;
+        let compact = Self {
+            len,
+            width,
+            words: data.slice(16..end),
+            endian,
+        };
+        Ok((compact, end))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, index: usize) -> u64 {
+        let width = self.width;
+        if width == 0 {
+            return 0;
+        }
+        let pos = index as u64 * width;
+        let (word, shift) = ((pos / 64) as usize, pos % 64);
+        let mut bits = self.word(word) >> shift;
+        if shift + width > 64 {
+            bits |= self.word(word + 1) << (64 - shift);
+        }
+        bits & (u64::MAX >> (64 - width))
+    }
+
+    /// Word `index`, 0 past the end so corrupted files do not panic.
+    #[inline]
+    fn word(&self, index: usize) -> u64 {
+        match self.words.get(index * 8..index * 8 + 8) {
+            Some(bytes) => self
+                .endian
+                .convert_u64(u64::from_ne_bytes(bytes.try_into().unwrap())),
+            None => 0,
+        }
+    }
+}
+
+/// A sequence written by [`write_dictionary`], read in place.
+#[derive(Default)]
+pub(crate) struct Dictionary {
+    dictionary: Compact,
+    ids: Compact,
+}
+
+impl Dictionary {
+    /// Load the sequence at the start of `data`, returning it and the length it takes.
+    pub(crate) fn load(data: &Data, endian: Endian) -> Result<(Self, usize)> {
+        let (dictionary, dictionary_len) = Compact::load(data, endian)?;
+        let (ids, ids_len) = Compact::load(&data.slice(dictionary_len..data.len()), endian)?;
+        Ok((Self { dictionary, ids }, dictionary_len + ids_len))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, index: usize) -> u64 {
+        self.dictionary.get(self.ids.get(index) as usize)
+    }
+}
@@ -0,0 +1,67 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+/// A cheaply cloneable view into an immutable, reference counted byte buffer.
+///
+/// Readers keep a `Data` of their section instead of pointers into it, so they share
+/// ownership of the underlying mapping and can be sent to and shared by other threads.
+#[derive(Clone)]
+pub struct Data {
+    owner: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    range: Range<usize>,
+}
+
+impl Data {
+    pub fn new<T>(owner: T) -> Self
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let len = owner.as_ref().len();
+        Self {
+            owner: Arc::new(owner),
+            range: 0..len,
+        }
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[u8] {
+        &(*self.owner).as_ref()[self.range.clone()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    /// A view of `range`, relative to this view. Panics if it is out of bounds.
+    pub fn slice(&self, range: Range<usize>) -> Data {
+        assert!(range.start <= range.end && range.end <= self.len());
+        Self {
+            owner: self.owner.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+}
+
+impl Default for Data {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl std::ops::Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for Data {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
@@ -0,0 +1,106 @@
+//! Training of compression dictionaries from sample values.
+//!
+//! A simplified form of the cover algorithm: values are cut into overlapping segments,
+//! each scored by how many sampled values contain its 8 byte substrings, and the best
+//! segments are picked greedily. Substrings of a picked segment no longer count for
+//! the others, so the dictionary does not repeat itself.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// At most this many bytes of values are sampled.
+const SAMPLE_BYTES: usize = 1 << 20;
+const KMER: usize = 8;
+const SEGMENT: usize = 48;
+const SEGMENT_STEP: usize = 16;
+
+fn kmers(bytes: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    bytes
+        .windows(KMER)
+        .map(|kmer| u64::from_le_bytes(kmer.try_into().unwrap()))
+}
+
+/// A dictionary of up to `size` bytes for compressing values like `values`. The most
+/// useful content comes last, closest to the compressed data.
+pub(crate) fn train(values: &[&[u8]], size: usize) -> Vec<u8> {
+    let total: usize = values.iter().map(|value| value.len()).sum();
+    let step = total.div_ceil(SAMPLE_BYTES).max(1);
+    let samples: Vec<&[u8]> = values
+        .iter()
+        .filter(|value| value.len() >= KMER)
+        .step_by(step)
+        .copied()
+        .collect();
+
+    // number of samples containing each substring
+    let mut frequency: HashMap<u64, u32> = HashMap::new();
+    let mut seen = HashSet::new();
+    for sample in &samples {
+        seen.clear();
+        for kmer in kmers(sample) {
+            if seen.insert(kmer) {
+                *frequency.entry(kmer).or_default() += 1;
+            }
+        }
+    }
+    // a substring of a single value does not help compress the others
+    frequency.retain(|_, count| *count > 1);
+
+    let segments: Vec<&[u8]> = samples
+        .iter()
+        .flat_map(|sample| {
+            (0..sample.len().saturating_sub(KMER) + 1)
+                .step_by(SEGMENT_STEP)
+                .map(|beg| &sample[beg..sample.len().min(beg + SEGMENT)])
+        })
+        .collect();
+    let segment_kmers: Vec<Vec<u64>> = segments
+        .iter()
+        .map(|segment| {
+            let mut kmers: Vec<u64> = kmers(segment)
+                .filter(|kmer| frequency.contains_key(kmer))
+                .collect();
+            kmers.sort_unstable();
+            kmers.dedup();
+            kmers
+        })
+        .collect();
+    let score = |index: usize, frequency: &HashMap<u64, u32>| -> u64 {
+        segment_kmers[index]
+            .iter()
+            .map(|kmer| frequency.get(kmer).copied().unwrap_or(0) as u64)
+            .sum()
+    };
+
+    let mut heap: BinaryHeap<(u64, usize)> = (0..segments.len())
+        .map(|index| (score(index, &frequency), index))
+        .filter(|(score, _)| *score > 0)
+        .collect();
+    let mut picked = Vec::new();
+    let mut len = 0;
+    while let Some((old_score, index)) = heap.pop() {
+        if len >= size {
+            break;
+        }
+        // scores only drop as substrings get covered, so a segment which still has
+        // its score is the best one left
+        let new_score = score(index, &frequency);
+        if new_score < old_score {
+            if new_score > 0 {
+                heap.push((new_score, index));
+            }
+            continue;
+        }
+        for kmer in &segment_kmers[index] {
+            frequency.remove(kmer);
+        }
+        len += segments[index].len();
+        picked.push(segments[index]);
+    }
+
+    let mut dictionary = Vec::with_capacity(len.min(size));
+    for segment in picked.iter().rev() {
+        dictionary.extend_from_slice(segment);
+    }
+    // keep the best content, at the end
+    dictionary.split_off(dictionary.len().saturating_sub(size))
+}
@@ -0,0 +1,234 @@
+//! Elias–Fano encoding of a non-decreasing sequence of integers.
+//!
+//! Each value is split into `low_bits` low bits, stored packed, and the remaining high
+//! bits, stored in unary as a set bit at position `(value >> low_bits) + index` of a
+//! bit vector. That takes about `2 + log2(universe / len)` bits per value.
+//!
+//! Finding the `i`-th set bit starts at a sampled position of every
+//! [`SELECT_SAMPLE`]-th one. At least half of the high bits are set, so the scan from
+//! a sample covers a bounded number of words.
+
+use crate::{any_as_u8_mut_slice, any_as_u8_slice, Data, Endian, Error, Result};
+
+const SELECT_SAMPLE: usize = 256;
+
+#[derive(Default, Clone, Copy)]
+#[repr(C, packed)]
+struct EliasFanoHeader {
+    len: u64,
+    low_bits: u64,
+    low_words: u64,
+    high_words: u64,
+    sample_words: u64,
+}
+
+/// Encode `values`, which must not decrease, as the header followed by the low, high
+/// and sample words, all `u64` in `endian`.
+pub(crate) fn write<W>(values: &[u64], writer: &mut W, endian: Endian) -> Result<()>
+where
+    W: std::io::Write,
+{
+    let len = values.len() as u64;
+    let universe = values.last().copied().unwrap_or(0);
+    let low_bits = match universe.checked_div(len) {
+        Some(ratio) if ratio > 0 => ratio.ilog2() as u64,
+        _ => 0,
+    };
+
+    let mut low = vec![0u64; (len * low_bits).div_ceil(64) as usize];
+    let high_len = (universe >> low_bits) + len;
+    let mut high = vec![0u64; high_len.div_ceil(64) as usize];
+    let mut samples = Vec::with_capacity(values.len().div_ceil(SELECT_SAMPLE));
+
+    let mut prev = 0;
+    for (i, value) in values.iter().copied().enumerate() {
+        if value < prev {
+            return Err(Error::InvalidConfig("elias-fano values must not decrease"));
+        }
+        prev = value;
+
+        if low_bits > 0 {
+            let bits = value & ((1 << low_bits) - 1);
+            let pos = i as u64 * low_bits;
+            let (word, shift) = ((pos / 64) as usize, pos % 64);
+            low[word] |= bits << shift;
+            if shift + low_bits > 64 {
+                low[word + 1] |= bits >> (64 - shift);
+            }
+        }
+
+        let pos = (value >> low_bits) + i as u64;
+        high[(pos / 64) as usize] |= 1 << (pos % 64);
+        if i % SELECT_SAMPLE == 0 {
+            samples.push(pos);
+        }
+    }
+
+    let header = EliasFanoHeader {
+        len: endian.convert_u64(len),
+        low_bits: endian.convert_u64(low_bits),
+        low_words: endian.convert_u64(low.len() as u64),
+        high_words: endian.convert_u64(high.len() as u64),
+        sample_words: endian.convert_u64(samples.len() as u64),
+    };
+    unsafe {
+        writer.write_all(any_as_u8_slice(&header))?;
+    }
+    for word in low.iter().chain(&high).chain(&samples) {
+        writer.write_all(&endian.convert_u64(*word).to_ne_bytes())?;
+    }
+    Ok(())
+}
+
+/// A sequence written by [`write`], read in place.
+#[derive(Default)]
+pub(crate) struct EliasFano {
+    header: EliasFanoHeader,
+    low: Data,
+    high: Data,
+    samples: Data,
+    endian: Endian,
+}
+
+impl EliasFano {
+    /// Load the sequence at the start of `data`, returning it and the length it takes.
+    pub(crate) fn load(data: &Data, endian: Endian) -> Result<(Self, usize)> {
+        let mut header = EliasFanoHeader::default();
+        let header_len = std::mem::size_of::<EliasFanoHeader>();
+        if data.len() < header_len {
+            return Err(Error::Corrupted("elias-fano header too small"));
+        }
+        unsafe {
+            any_as_u8_mut_slice(&mut header).copy_from_slice(&data[..header_len]);
+        }
+        header.len = endian.convert_u64(header.len);
+        header.low_bits = endian.convert_u64(header.low_bits);
+        header.low_words = endian.convert_u64(header.low_words);
+        header.high_words = endian.convert_u64(header.high_words);
+        header.sample_words = endian.convert_u64(header.sample_words);
+
+        let (len, low_bits) = (header.len, header.low_bits);
+        let (low_words, high_words, sample_words) =
+            (header.low_words, header.high_words, header.sample_words);
+        if low_bits >= 64
+            || len.checked_mul(low_bits).map(|bits| bits.div_ceil(64)) != Some(low_words)
+            || high_words < len.div_ceil(64)
+            || sample_words != len.div_ceil(SELECT_SAMPLE as u64)
+        {
+            return Err(Error::Corrupted("elias-fano header out of bounds"));
+        }
+        let words = [low_words, high_words, sample_words]
+            .iter()
+            .try_fold(0u64, |sum, words| sum.checked_add(*words))
+            .and_then(|words| words.checked_mul(8))
+            .and_then(|bytes| (bytes as usize).checked_add(header_len))
+            .filter(|end| *end <= data.len())
+            .ok_or(Error::Corrupted("elias-fano words out of bounds"))?;
+
+        let low_end = header_len + low_words as usize * 8;
+        let high_end = low_end + high_words as usize * 8;
+        let ef = Self {
+            header,
+            low: data.slice(header_len..low_end),
+            high: data.slice(low_end..high_end),
+            samples: data.slice(high_end..words),
+            endian,
+        };
+        Ok((ef, words))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.header.len as usize
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, index: usize) -> u64 {
+        self.value(index, self.select(index))
+    }
+
+    /// Value `index` and the one after it.
+    #[inline]
+    pub(crate) fn get_pair(&self, index: usize) -> (u64, u64) {
+        let pos = self.select(index);
+        let next = self.next_one(pos);
+        (self.value(index, pos), self.value(index + 1, next))
+    }
+
+    #[inline]
+    fn value(&self, index: usize, high_pos: u64) -> u64 {
+        let high = high_pos.wrapping_sub(index as u64);
+        (high << self.header.low_bits) | self.low(index)
+    }
+
+    #[inline]
+    fn low(&self, index: usize) -> u64 {
+        let low_bits = self.header.low_bits;
+        if low_bits == 0 {
+            return 0;
+        }
+        let pos = index as u64 * low_bits;
+        let (word, shift) = ((pos / 64) as usize, pos % 64);
+        let mut bits = self.word(&self.low, word) >> shift;
+        if shift + low_bits > 64 {
+            bits |= self.word(&self.low, word + 1) << (64 - shift);
+        }
+        bits & ((1 << low_bits) - 1)
+    }
+
+    /// Position of the `index`-th set high bit, `u64::MAX` past the end.
+    #[inline]
+    fn select(&self, index: usize) -> u64 {
+        let sample = index / SELECT_SAMPLE;
+        if sample >= self.header.sample_words as usize {
+            return u64::MAX;
+        }
+        let pos = self.word(&self.samples, sample);
+        let mut remaining = index % SELECT_SAMPLE;
+        let mut word_index = (pos / 64) as usize;
+        let mut word = self.word(&self.high, word_index) & (u64::MAX << (pos % 64));
+        loop {
+            let ones = word.count_ones() as usize;
+            if remaining < ones {
+                for _ in 0..remaining {
+                    word &= word - 1;
+                }
+                return word_index as u64 * 64 + word.trailing_zeros() as u64;
+            }
+            remaining -= ones;
+            word_index += 1;
+            if word_index >= self.header.high_words as usize {
+                return u64::MAX;
+            }
+            word = self.word(&self.high, word_index);
+        }
+    }
+
+    /// Position of the first set high bit after `pos`, `u64::MAX` if there is none.
+    #[inline]
+    fn next_one(&self, pos: u64) -> u64 {
+        let Some(pos) = pos.checked_add(1) else {
+            return u64::MAX;
+        };
+        let mut word_index = (pos / 64) as usize;
+        let mut word = self.word(&self.high, word_index) & (u64::MAX << (pos % 64));
+        while word == 0 {
+            word_index += 1;
+            if word_index >= self.header.high_words as usize {
+                return u64::MAX;
+            }
+            word = self.word(&self.high, word_index);
+        }
+        word_index as u64 * 64 + word.trailing_zeros() as u64
+    }
+
+    /// Word `index` of `words`, 0 past the end so corrupted files do not panic.
+    #[inline]
+    fn word(&self, words: &Data, index: usize) -> u64 {
+        match words.get(index * 8..index * 8 + 8) {
+            Some(bytes) => self
+                .endian
+                .convert_u64(u64::from_ne_bytes(bytes.try_into().unwrap())),
+            None => 0,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+/// Byte order of the integers in a file.
+///
+/// Files are written in the byte order chosen by the serializer, native by default,
+/// and readers swap integers on access when it differs from the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Default for Endian {
+    fn default() -> Self {
+        Self::NATIVE
+    }
+}
+
+impl Endian {
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: Endian = Endian::Little;
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: Endian = Endian::Big;
+
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Endian::Little => 0,
+            Endian::Big => 1,
+        }
+    }
+
+    pub(crate) fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Endian::Little),
+            1 => Some(Endian::Big),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn is_native(self) -> bool {
+        self == Self::NATIVE
+    }
+
+    /// Convert between native and `self` byte order, which is the same operation in
+    /// both directions.
+    #[inline]
+    pub(crate) fn convert_u16(self, v: u16) -> u16 {
+        if self.is_native() {
+            v
+        } else {
+            v.swap_bytes()
+        }
+    }
+
+    #[inline]
+    pub(crate) fn convert_u32(self, v: u32) -> u32 {
+        if self.is_native() {
+            v
+        } else {
+            v.swap_bytes()
+        }
+    }
+
+    #[inline]
+    pub(crate) fn convert_u64(self, v: u64) -> u64 {
+        if self.is_native() {
+            v
+        } else {
+            v.swap_bytes()
+        }
+    }
+
+    /// Read the `index`-th `u32` of `bytes`, which has no alignment requirement.
+    #[inline]
+    pub(crate) fn read_u32(self, bytes: &[u8], index: usize) -> u32 {
+        let beg = index * std::mem::size_of::<u32>();
+        let v = u32::from_ne_bytes(bytes[beg..beg + 4].try_into().unwrap());
+        self.convert_u32(v)
+    }
+
+    /// Read the `index`-th `u64` of `bytes`, which has no alignment requirement.
+    #[inline]
+    pub(crate) fn read_u64(self, bytes: &[u8], index: usize) -> u64 {
+        let beg = index * std::mem::size_of::<u64>();
+        let v = u64::from_ne_bytes(bytes[beg..beg + 8].try_into().unwrap());
+        self.convert_u64(v)
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// No perfect hash function was found within the configured retries.
+    ConstructionFailed,
+    /// A configuration value is out of its valid range.
+    InvalidConfig(&'static str),
+    /// The file is truncated or does not contain a valid map.
+    Corrupted(&'static str),
+    VersionMismatch {
+        expected: u8,
+        found: u8,
+    },
+    /// The values do not fit the offsets of the value encoding.
+    ValueTooLarge,
+    /// A fixed width value encoding was given values of different lengths.
+    WidthMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// The reader has no map loaded.
+    NotLoaded,
+    /// The loaded buffer is not aligned as the value type needs.
+    Unaligned,
+    /// The file was written with a `kind` of component this build does not know.
+    UnknownType {
+        kind: &'static str,
+        id: u32,
+    },
+    /// The file was written with a different `kind` of component than the reader uses.
+    TypeMismatch {
+        kind: &'static str,
+        expected: u32,
+        found: u32,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::ConstructionFailed => write!(f, "perfect hash construction failed"),
+            Error::InvalidConfig(msg) => write!(f, "invalid config: {}", msg),
+            Error::Corrupted(msg) => write!(f, "corrupted file: {}", msg),
+            Error::VersionMismatch { expected, found } => {
+                write!(
+                    f,
+                    "version mismatch: expected {}, found {}",
+                    expected, found
+                )
+            }
+            Error::ValueTooLarge => write!(f, "value section too large"),
+            Error::WidthMismatch { expected, found } => write!(
+                f,
+                "value width mismatch: expected {} bytes, found {}",
+                expected, found
+            ),
+            Error::NotLoaded => write!(f, "no map loaded"),
+            Error::Unaligned => write!(f, "buffer not aligned for the value type"),
+            Error::UnknownType { kind, id } => write!(f, "unknown {} id {}", kind, id),
+            Error::TypeMismatch {
+                kind,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} mismatch: expected id {}, found {}",
+                kind, expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -1,25 +1,172 @@
 pub trait Hasher: std::hash::Hasher + Default {
+    /// Identifies the hash function in the file header, so a reader with a different
+    /// hasher fails to load instead of looking up wrong slots.
+    const ID: u32;
+
+    /// A hasher whose output depends on `seed`. Seed 0 must give the same hashes as
+    /// `Default`.
+    fn with_seed(seed: u64) -> Self;
+
     fn finish_u128(&self) -> u128;
 }
 
-#[derive(Debug, Default)]
-pub struct CityHash {
-    inner: u128,
+const INLINE_LEN: usize = 64;
+
+/// Bytes written to a hasher, so that how a key is split into `write` calls does not
+/// matter. Short keys stay in an inline buffer.
+#[derive(Debug, Clone)]
+struct KeyBuffer {
+    inline: [u8; INLINE_LEN],
+    len: usize,
+    spill: Vec<u8>,
 }
 
-impl std::hash::Hasher for CityHash {
-    fn write(&mut self, data: &[u8]) {
-        let hash = naive_cityhash::cityhash128(data);
-        self.inner = (self.inner << 1) ^ (((hash.hi as u128) << 64) | (hash.lo as u128))
+impl Default for KeyBuffer {
+    fn default() -> Self {
+        Self {
+            inline: [0; INLINE_LEN],
+            len: 0,
+            spill: Vec::new(),
+        }
     }
+}
 
-    fn finish(&self) -> u64 {
-        self.inner as u64
+impl KeyBuffer {
+    fn bytes(&self) -> &[u8] {
+        if self.spill.is_empty() {
+            &self.inline[..self.len]
+        } else {
+            &self.spill
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if self.spill.is_empty() && self.len + data.len() <= INLINE_LEN {
+            self.inline[self.len..self.len + data.len()].copy_from_slice(data);
+            self.len += data.len();
+            return;
+        }
+        if self.spill.is_empty() {
+            self.spill.extend_from_slice(&self.inline[..self.len]);
+        }
+        self.spill.extend_from_slice(data);
     }
 }
 
-impl Hasher for CityHash {
-    fn finish_u128(&self) -> u128 {
-        self.inner
+macro_rules! impl_buffered_hasher {
+    ($name:ident, $id:expr, $hash:expr) => {
+        impl std::hash::Hasher for $name {
+            fn write(&mut self, data: &[u8]) {
+                self.buffer.write(data);
+            }
+
+            fn finish(&self) -> u64 {
+                self.finish_u128() as u64
+            }
+        }
+
+        impl Hasher for $name {
+            const ID: u32 = $id;
+
+            fn with_seed(seed: u64) -> Self {
+                Self {
+                    buffer: KeyBuffer::default(),
+                    seed,
+                }
+            }
+
+            fn finish_u128(&self) -> u128 {
+                $hash(self.buffer.bytes(), self.seed)
+            }
+        }
+    };
+}
+
+fn city_u128(data: &[u8], seed: u64) -> u128 {
+    let hash = if seed == 0 {
+        naive_cityhash::cityhash128(data)
+    } else {
+        naive_cityhash::cityhash128_with_seed(data, naive_cityhash::U128::new(seed, 0))
+    };
+    ((hash.hi as u128) << 64) | (hash.lo as u128)
+}
+
+/// CityHash128 over all bytes written so far.
+#[derive(Debug, Default, Clone)]
+pub struct CityHash {
+    buffer: KeyBuffer,
+    seed: u64,
+}
+
+impl_buffered_hasher!(CityHash, 0, city_u128);
+
+/// XXH3 with 128 bit output over all bytes written so far.
+#[derive(Debug, Default, Clone)]
+pub struct Xxh3 {
+    buffer: KeyBuffer,
+    seed: u64,
+}
+
+impl_buffered_hasher!(Xxh3, 1, xxhash_rust::xxh3::xxh3_128_with_seed);
+
+/// The finalizer of MurmurHash3, a bijection with full avalanche.
+#[inline]
+pub(crate) fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+    k ^= k >> 33;
+    k
+}
+
+fn wyhash_u128(data: &[u8], seed: u64) -> u128 {
+    // wyhash gives 64 bits, the high half is derived from them
+    let lo = wyhash::wyhash(data, seed);
+    let hi = fmix64(lo ^ 0xA076_1D64_78BD_642F);
+    ((hi as u128) << 64) | (lo as u128)
+}
+
+/// wyhash over all bytes written so far.
+#[derive(Debug, Default, Clone)]
+pub struct WyHash {
+    buffer: KeyBuffer,
+    seed: u64,
+}
+
+impl_buffered_hasher!(WyHash, 2, wyhash_u128);
+
+#[inline]
+fn folded_multiply(a: u64, b: u64) -> u64 {
+    let r = (a as u128) * (b as u128);
+    (r as u64) ^ ((r >> 64) as u64)
+}
+
+fn int_u128(data: &[u8], seed: u64) -> u128 {
+    const M0: u64 = 0x9E37_79B9_7F4A_7C15;
+    const M1: u64 = 0xA076_1D64_78BD_642F;
+    const M2: u64 = 0xE703_7ED1_A0B4_28DB;
+
+    let mut acc = seed ^ (data.len() as u64).wrapping_mul(M0);
+    for chunk in data.chunks(8) {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        acc = folded_multiply(acc ^ u64::from_le_bytes(word), M1);
     }
+    let lo = fmix64(acc ^ M0);
+    let hi = fmix64(acc ^ M2);
+    ((hi as u128) << 64) | (lo as u128)
+}
+
+/// Multiply-shift hashing of 8 byte words, one multiplication per word, finished with
+/// the MurmurHash3 finalizer so the bits used for the hash index are independent.
+///
+/// Meant for integer keys, where it is much faster than the general purpose hashers.
+/// It still accepts any key, but mixes long keys less thoroughly.
+#[derive(Debug, Default, Clone)]
+pub struct IntHash {
+    buffer: KeyBuffer,
+    seed: u64,
 }
+
+impl_buffered_hasher!(IntHash, 3, int_u128);
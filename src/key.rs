@@ -0,0 +1,144 @@
+/// A key with a byte encoding which is the same on every platform and Rust version.
+///
+/// The encoding is what gets hashed, so a map built on one machine answers lookups on
+/// another. Integers are little endian, `usize`/`isize` are widened to 64 bits, and
+/// strings are their UTF-8 bytes, so `str`, `String` and `[u8]` of the same content are
+/// the same key.
+pub trait PHashKey {
+    /// The encoding, borrowed where the key holds it and on the stack for scalars, so
+    /// lookups do not allocate.
+    type Bytes<'a>: AsRef<[u8]>
+    where
+        Self: 'a;
+
+    fn as_key_bytes(&self) -> Self::Bytes<'_>;
+}
+
+impl<T: PHashKey + ?Sized> PHashKey for &T {
+    type Bytes<'a>
+        = T::Bytes<'a>
+    where
+        Self: 'a;
+
+    fn as_key_bytes(&self) -> Self::Bytes<'_> {
+        (**self).as_key_bytes()
+    }
+}
+
+impl<T: PHashKey + ?Sized> PHashKey for Box<T> {
+    type Bytes<'a>
+        = T::Bytes<'a>
+    where
+        Self: 'a;
+
+    fn as_key_bytes(&self) -> Self::Bytes<'_> {
+        (**self).as_key_bytes()
+    }
+}
+
+impl PHashKey for [u8] {
+    type Bytes<'a> = &'a [u8];
+
+    fn as_key_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<const N: usize> PHashKey for [u8; N] {
+    type Bytes<'a> = &'a [u8];
+
+    fn as_key_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl PHashKey for Vec<u8> {
+    type Bytes<'a> = &'a [u8];
+
+    fn as_key_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl PHashKey for str {
+    type Bytes<'a> = &'a [u8];
+
+    fn as_key_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl PHashKey for String {
+    type Bytes<'a> = &'a [u8];
+
+    fn as_key_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+macro_rules! impl_int_key {
+    ($($ty:ty => $as:ty),*) => {
+        $(
+            impl PHashKey for $ty {
+                type Bytes<'a> = [u8; std::mem::size_of::<$as>()];
+
+                fn as_key_bytes(&self) -> Self::Bytes<'_> {
+                    (*self as $as).to_le_bytes()
+                }
+            }
+        )*
+    };
+}
+
+impl_int_key!(
+    u8 => u8, u16 => u16, u32 => u32, u64 => u64, u128 => u128, usize => u64,
+    i8 => i8, i16 => i16, i32 => i32, i64 => i64, i128 => i128, isize => i64
+);
+
+impl PHashKey for bool {
+    type Bytes<'a> = [u8; 1];
+
+    fn as_key_bytes(&self) -> [u8; 1] {
+        [*self as u8]
+    }
+}
+
+impl PHashKey for char {
+    type Bytes<'a> = [u8; 4];
+
+    fn as_key_bytes(&self) -> [u8; 4] {
+        (*self as u32).to_le_bytes()
+    }
+}
+
+// Every element but the last is prefixed with its length, so that ("ab", "c") and
+// ("a", "bc") are different keys.
+macro_rules! impl_tuple_key {
+    ($($name:ident)+; $last:ident) => {
+        impl<$($name: PHashKey,)+ $last: PHashKey + ?Sized> PHashKey for ($($name,)+ $last,) {
+            type Bytes<'a>
+                = Vec<u8>
+            where
+                Self: 'a;
+
+            #[allow(non_snake_case)]
+            fn as_key_bytes(&self) -> Vec<u8> {
+                let ($($name,)+ $last,) = self;
+                let mut bytes = Vec::new();
+                $(
+                    let part = $name.as_key_bytes();
+                    let part = part.as_ref();
+                    bytes.extend_from_slice(&(part.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(part);
+                )+
+                bytes.extend_from_slice($last.as_key_bytes().as_ref());
+                bytes
+            }
+        }
+    };
+}
+
+impl_tuple_key!(A; B);
+impl_tuple_key!(A B; C);
+impl_tuple_key!(A B C; D);
+impl_tuple_key!(A B C D; E);
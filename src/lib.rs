@@ -1,9 +1,32 @@
-use std::{fs::File, hash::Hash, marker::PhantomData};
+use std::{borrow::Cow, fs::File, io::Seek, marker::PhantomData};
 
+pub mod any;
+pub mod bbhash;
 pub mod chd;
+mod checksum;
+mod compact;
+pub mod data;
+mod dictionary;
+mod elias_fano;
+pub mod endian;
+pub mod error;
 pub mod hasher;
+pub mod key;
+pub mod pthash;
+pub mod recsplit;
+pub mod typed;
 pub mod value;
+pub mod verification;
+pub use any::AnyPerfectHashMap;
+use checksum::ChecksumWriter;
+pub use data::Data;
+pub use endian::Endian;
+pub use error::{Error, Result};
 pub use hasher::Hasher;
+pub use key::PHashKey;
+pub use typed::{PHashValue, PerfectHashMap, PerfectHashMapWriter};
+pub use verification::KeyVerification;
+use verification::KeyVerifier;
 
 unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
     ::std::slice::from_raw_parts((p as *const T) as *const u8, ::std::mem::size_of::<T>())
@@ -14,36 +37,46 @@ unsafe fn any_as_u8_mut_slice<T: Sized>(p: &mut T) -> &mut [u8] {
 }
 
 unsafe fn any_array_as_u8_slice<T: Sized>(p: &[T]) -> &[u8] {
-    ::std::slice::from_raw_parts(
-        p.as_ptr() as *const u8,
-        ::std::mem::size_of::<T>() * p.len(),
-    )
+    ::std::slice::from_raw_parts(p.as_ptr() as *const u8, ::std::mem::size_of_val(p))
 }
 
 #[allow(unused)]
 unsafe fn any_array_as_u8_mut_slice<T: Sized>(p: &mut [T]) -> &mut [u8] {
-    ::std::slice::from_raw_parts_mut(
-        p.as_mut_ptr() as *mut u8,
-        ::std::mem::size_of::<T>() * p.len(),
-    )
+    ::std::slice::from_raw_parts_mut(p.as_mut_ptr() as *mut u8, ::std::mem::size_of_val(p))
 }
 
 type HashIndex = u32;
 
+const SECTION_ALIGN: u64 = 8;
+
+/// Pad the stream so the next section starts at a multiple of [`SECTION_ALIGN`].
+fn pad_section<W>(writer: &mut W) -> std::io::Result<()>
+where
+    W: std::io::Write + std::io::Seek,
+{
+    let pos = writer.stream_position()?;
+    let padding = pos.next_multiple_of(SECTION_ALIGN) - pos;
+    writer.write_all(&[0u8; SECTION_ALIGN as usize][..padding as usize])
+}
+
 pub struct HashIndexSerializeInfo {
     pub max_hash_index: u32,
 }
 
 pub trait PHashIndexSerializer<K, H: Hasher>
 where
-    K: Hash,
+    K: PHashKey + ?Sized,
 {
+    /// Identifies the index algorithm in the file header. Shared with the matching
+    /// deserializer.
+    const ID: u32;
     type Deserializer;
-    fn generate<'a, W>(
+    fn generate<W>(
         &mut self,
-        keys: &Vec<&'a K>,
+        keys: &[&K],
         writer: &mut W,
-    ) -> Option<HashIndexSerializeInfo>
+        endian: Endian,
+    ) -> Result<HashIndexSerializeInfo>
     where
         W: std::io::Write + std::io::Seek;
 
@@ -52,36 +85,251 @@ where
 
 pub trait PHashIndexDeserializer<K, H: Hasher>
 where
-    K: Hash,
+    K: PHashKey + ?Sized,
 {
+    const ID: u32;
     type Serializer;
-    fn load<'a>(&'a mut self, ptr: &'a [u8]) -> Option<()>;
+    fn load(&mut self, data: Data, endian: Endian) -> Result<()>;
     fn get_hash_index(&self, key: &K) -> HashIndex;
+    /// Upper bound (exclusive) of the indexes returned by `get_hash_index`.
+    fn max_hash_index(&self) -> HashIndex;
+
+    /// Figures of the loaded index, for inspection tools.
+    fn stats(&self) -> IndexStats {
+        IndexStats {
+            max_hash_index: self.max_hash_index(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct IndexStats {
+    pub max_hash_index: HashIndex,
+    /// Algorithm specific figures, e.g. the CHD bucket count.
+    pub fields: Vec<(&'static str, u64)>,
+    /// Histogram of a per bucket parameter, e.g. the CHD displacement, as
+    /// `(value, count)` pairs sorted by value.
+    pub histogram: Vec<(u64, u64)>,
 }
 
 pub trait PHashIndexEncoding {}
 
 pub trait PHashValueSerializer {
-    fn write_all<W>(&self, values: &Vec<&[u8]>, writer: &mut W) -> Option<()>
+    /// Identifies the value encoding in the file header. Shared with the matching
+    /// deserializer.
+    const ID: u32;
+
+    /// What `write_index` computes for `write_payload`, e.g. the compressed values.
+    type Prepared;
+
+    /// Write the part of the section which locates a value by its index, e.g. the
+    /// section header and an offset table.
+    fn write_index<W>(
+        &self,
+        values: &[&[u8]],
+        writer: &mut W,
+        endian: Endian,
+    ) -> Result<Self::Prepared>
     where
         W: std::io::Write;
+
+    /// Write the value bytes, directly following `write_index` of the same `values`,
+    /// which returned `prepared`.
+    fn write_payload<W>(
+        &self,
+        values: &[&[u8]],
+        prepared: Self::Prepared,
+        writer: &mut W,
+        endian: Endian,
+    ) -> Result<()>
+    where
+        W: std::io::Write;
+
+    fn write_all<W>(&self, values: &[&[u8]], writer: &mut W, endian: Endian) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        let prepared = self.write_index(values, writer, endian)?;
+        self.write_payload(values, prepared, writer, endian)
+    }
 }
 
 pub trait PHashValueDeserializer {
-    fn load<'a>(&'a mut self, ptr: &'a [u8]) -> Option<()>;
-    fn get<'a>(&'a self, index: HashIndex) -> &'a [u8];
+    const ID: u32;
+    /// What `get` returns: `&[u8]` for values read in place, or an owned buffer for
+    /// encodings which decode on access.
+    type Value<'a>: std::ops::Deref<Target = [u8]> + Into<Cow<'a, [u8]>>
+    where
+        Self: 'a;
+    /// `index` is the part of the section written by `write_index`, `payload` the rest.
+    fn load(&mut self, index: Data, payload: Data, endian: Endian) -> Result<()>;
+    fn get(&self, index: HashIndex) -> Self::Value<'_>;
+    /// Number of slots with a value.
+    fn count(&self) -> usize;
+
+    /// A reader with the settings of this one and nothing loaded. Maps load into it
+    /// and keep the current reader when loading fails.
+    fn unloaded(&self) -> Self
+    where
+        Self: Sized + Default,
+    {
+        Self::default()
+    }
 }
 
+const MAGIC: [u8; 4] = *b"PHSH";
+/// The only version read. Files of earlier versions, including those hashed with the
+/// legacy CityHash mixer, are rejected.
+const VERSION: u8 = 8;
+
 #[derive(Default)]
-#[repr(packed)]
+#[repr(C, packed)]
 #[allow(unused)]
 struct PerfectHashMapHeader {
+    magic: [u8; 4],
     endian: u8,
     version: u8,
-    _reserved0: u16,
+    header_size: u16,
     flag: u32,
+    /// `Hasher::ID` of the hasher the map was built with.
+    hasher_id: u32,
     index_size: u64,
     value_size: u64,
+    verify_size: u64,
+    /// Bytes of the value section written by `PHashValueSerializer::write_index`.
+    value_index_size: u64,
+    index_checksum: u32,
+    value_index_checksum: u32,
+    value_payload_checksum: u32,
+    verify_checksum: u32,
+    /// `PHashIndexSerializer::ID` of the index.
+    index_id: u32,
+    /// `PHashValueSerializer::ID` of the values.
+    value_id: u32,
+    key_count: u64,
+}
+
+impl PerfectHashMapHeader {
+    /// Convert the multi-byte fields between native and `endian` byte order.
+    fn convert(mut self, endian: Endian) -> Self {
+        self.header_size = endian.convert_u16(self.header_size);
+        self.flag = endian.convert_u32(self.flag);
+        self.hasher_id = endian.convert_u32(self.hasher_id);
+        self.index_size = endian.convert_u64(self.index_size);
+        self.value_size = endian.convert_u64(self.value_size);
+        self.verify_size = endian.convert_u64(self.verify_size);
+        self.value_index_size = endian.convert_u64(self.value_index_size);
+        self.index_checksum = endian.convert_u32(self.index_checksum);
+        self.value_index_checksum = endian.convert_u32(self.value_index_checksum);
+        self.value_payload_checksum = endian.convert_u32(self.value_payload_checksum);
+        self.verify_checksum = endian.convert_u32(self.verify_checksum);
+        self.index_id = endian.convert_u32(self.index_id);
+        self.value_id = endian.convert_u32(self.value_id);
+        self.key_count = endian.convert_u64(self.key_count);
+        self
+    }
+
+    fn endian(&self) -> Endian {
+        Endian::from_u8(self.endian).unwrap_or_default()
+    }
+
+    fn info(&self, file_size: usize) -> MapInfo {
+        MapInfo {
+            version: self.version,
+            endian: self.endian(),
+            verification: KeyVerification::from_flag(self.flag).unwrap_or_default(),
+            hasher_id: self.hasher_id,
+            index_id: self.index_id,
+            value_id: self.value_id,
+            key_count: self.key_count,
+            header_size: self.header_size as u64,
+            index_size: self.index_size,
+            value_size: self.value_size,
+            value_index_size: self.value_index_size,
+            verify_size: self.verify_size,
+            file_size: file_size as u64,
+        }
+    }
+
+    /// Read and validate the header, including that all sections fit into `data`.
+    fn parse(data: &[u8]) -> Result<Self> {
+        let header_len = std::mem::size_of::<Self>();
+        if data.len() < header_len {
+            return Err(Error::Corrupted("file too small"));
+        }
+        let mut header = Self::default();
+        unsafe {
+            any_as_u8_mut_slice(&mut header).copy_from_slice(&data[..header_len]);
+        }
+        if header.magic != MAGIC {
+            return Err(Error::Corrupted("bad magic number"));
+        }
+        if header.version != VERSION {
+            return Err(Error::VersionMismatch {
+                expected: VERSION,
+                found: header.version,
+            });
+        }
+        let endian = Endian::from_u8(header.endian).ok_or(Error::Corrupted("unknown endian"))?;
+        let header = header.convert(endian);
+        if header.header_size as usize != header_len {
+            return Err(Error::Corrupted("bad header size"));
+        }
+        if header.value_index_size > header.value_size {
+            return Err(Error::Corrupted("value index size out of bounds"));
+        }
+        let total = [header.index_size, header.value_size, header.verify_size]
+            .iter()
+            .try_fold(header_len as u64, |sum, size| sum.checked_add(*size));
+        match total {
+            Some(total) if total <= data.len() as u64 => Ok(header),
+            _ => Err(Error::Corrupted("section size out of bounds")),
+        }
+    }
+
+    /// Recompute the section checksums over `data`, which `parse` has accepted. The
+    /// header itself has no checksum; `parse` validates its fields instead.
+    fn verify_checksums(&self, data: &[u8]) -> Result<()> {
+        let index_beg = self.header_size as usize;
+        let value_beg = index_beg + self.index_size as usize;
+        let payload_beg = value_beg + self.value_index_size as usize;
+        let verify_beg = value_beg + self.value_size as usize;
+        let verify_end = verify_beg + self.verify_size as usize;
+
+        let sections = [
+            (
+                index_beg,
+                value_beg,
+                self.index_checksum,
+                "index checksum mismatch",
+            ),
+            (
+                value_beg,
+                payload_beg,
+                self.value_index_checksum,
+                "value index checksum mismatch",
+            ),
+            (
+                payload_beg,
+                verify_beg,
+                self.value_payload_checksum,
+                "value payload checksum mismatch",
+            ),
+            (
+                verify_beg,
+                verify_end,
+                self.verify_checksum,
+                "key verification checksum mismatch",
+            ),
+        ];
+        for (beg, end, expected, msg) in sections {
+            if checksum::checksum(&data[beg..end]) != expected {
+                return Err(Error::Corrupted(msg));
+            }
+        }
+        Ok(())
+    }
 }
 
 pub struct PerfectHashMapSerializer<H, K, I, V>
@@ -89,10 +337,12 @@ where
     I: PHashIndexSerializer<K, H>,
     V: PHashValueSerializer,
     H: Hasher,
-    K: Hash,
+    K: PHashKey,
 {
     index_serializer: I,
     value_serializer: V,
+    verification: KeyVerification,
+    endian: Endian,
     _pd0: PhantomData<H>,
     _pd1: PhantomData<K>,
 }
@@ -102,18 +352,34 @@ where
     I: PHashIndexSerializer<K, H>,
     V: PHashValueSerializer,
     H: Hasher,
-    K: Hash,
+    K: PHashKey,
 {
     pub fn new(index_serializer: I, value_serializer: V) -> Self {
         Self {
             index_serializer,
             value_serializer,
-            _pd0: PhantomData::default(),
-            _pd1: PhantomData::default(),
+            verification: KeyVerification::None,
+            endian: Endian::NATIVE,
+            _pd0: PhantomData,
+            _pd1: PhantomData,
         }
     }
 
-    pub fn write_to_file<'a, P>(&mut self, kvs: &Vec<(K, &'a [u8])>, path: P)
+    /// Store verification data so that lookups of absent keys return `None`.
+    pub fn verification(mut self, verification: KeyVerification) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Byte order of the written file. Readers on a host with the other byte order
+    /// swap integers on access, so keep the default native order unless the file is
+    /// mostly read on such hosts.
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    pub fn write_to_file<P>(&mut self, kvs: &[(K, &[u8])], path: P) -> Result<()>
     where
         P: AsRef<std::path::Path>,
     {
@@ -122,43 +388,55 @@ where
             .truncate(true)
             .create(true)
             .read(true)
-            .open(path.as_ref())
-            .unwrap();
+            .open(path.as_ref())?;
         self.write_to(kvs, file)
     }
-    pub fn write_to<'a, W>(&mut self, kvs: &Vec<(K, &'a [u8])>, mut writer: W)
+    pub fn write_to<W>(&mut self, kvs: &[(K, &[u8])], mut output: W) -> Result<()>
     where
         W: std::io::Write + std::io::Seek,
     {
-        #[cfg(target_endian = "big")]
-        let endian = 1u8;
-        #[cfg(target_endian = "little")]
-        let endian = 0u8;
+        if let KeyVerification::Fingerprint(width) = self.verification {
+            if !(1..=8).contains(&width) {
+                return Err(Error::InvalidConfig(
+                    "fingerprint width must be 1..=8 bytes",
+                ));
+            }
+        }
+
+        let header_len = std::mem::size_of::<PerfectHashMapHeader>() as u64;
         let mut header = PerfectHashMapHeader {
-            endian,
-            version: 0,
-            _reserved0: 0,
-            flag: 0,
-            index_size: 0 as u64,
-            value_size: 0 as u64,
+            magic: MAGIC,
+            endian: self.endian.to_u8(),
+            version: VERSION,
+            header_size: header_len as u16,
+            flag: self.verification.flag(),
+            hasher_id: H::ID,
+            index_id: I::ID,
+            value_id: V::ID,
+            key_count: kvs.len() as u64,
+            ..Default::default()
         };
-        let header_len = std::mem::size_of::<PerfectHashMapHeader>() as u64;
-        writer.seek(std::io::SeekFrom::Start(header_len)).unwrap();
+        output.seek(std::io::SeekFrom::Start(header_len))?;
+        let mut writer = ChecksumWriter::new(&mut output);
 
         let mut keys: Vec<&K> = kvs.iter().map(|v| &v.0).collect();
 
-        let index_info = self.index_serializer.generate(&keys, &mut writer).unwrap();
-        let index_size = writer.stream_position().unwrap() - header_len;
+        let index_info = self
+            .index_serializer
+            .generate(&keys, &mut writer, self.endian)?;
+        pad_section(&mut writer)?;
+        let index_size = writer.stream_position()? - header_len;
+        header.index_checksum = writer.take();
 
         // release keys memory
         keys.clear();
 
-        let mut values = Vec::new();
-        unsafe {
-            values.resize(
-                index_info.max_hash_index as usize,
-                std::slice::from_raw_parts(std::ptr::null(), 0),
-            );
+        let mut values: Vec<&[u8]> = Vec::new();
+        values.resize(index_info.max_hash_index as usize, &[]);
+
+        let mut slot_keys: Vec<Option<&K>> = Vec::new();
+        if self.verification != KeyVerification::None {
+            slot_keys.resize(index_info.max_hash_index as usize, None);
         }
 
         let mut used = bitvec::vec::BitVec::<usize>::new();
@@ -166,39 +444,74 @@ where
 
         for (key, value) in kvs {
             let idx = self.index_serializer.pick(key);
-            unsafe {
-                if *used.get_unchecked(idx as usize) {
-                    panic!("oops {} {}", idx, index_info.max_hash_index);
-                }
+            match used.get(idx as usize) {
+                Some(bit) if !*bit => {}
+                // duplicated keys or a broken index
+                _ => return Err(Error::ConstructionFailed),
             }
             used.set(idx as usize, true);
             values[idx as usize] = value;
+            if let Some(slot) = slot_keys.get_mut(idx as usize) {
+                *slot = Some(key);
+            }
         }
 
-        let _ = self
+        let prepared = self
             .value_serializer
-            .write_all(&values, &mut writer)
-            .unwrap();
+            .write_index(&values, &mut writer, self.endian)?;
+        header.value_index_size = writer.stream_position()? - header_len - index_size;
+        header.value_index_checksum = writer.take();
 
-        let value_size = writer.stream_position().unwrap() - header_len - index_size;
+        self.value_serializer
+            .write_payload(&values, prepared, &mut writer, self.endian)?;
+        pad_section(&mut writer)?;
+        let value_size = writer.stream_position()? - header_len - index_size;
+        header.value_payload_checksum = writer.take();
 
-        header.index_size = index_size as u64;
-        header.value_size = value_size as u64;
-        let pos = writer.stream_position().unwrap();
+        verification::write_verification::<K, H, _>(
+            self.verification,
+            &slot_keys,
+            &mut writer,
+            self.endian,
+        )?;
+        let verify_size = writer.stream_position()? - header_len - index_size - value_size;
+        header.verify_checksum = writer.take();
 
-        writer.seek(std::io::SeekFrom::Start(0)).unwrap();
+        header.index_size = index_size;
+        header.value_size = value_size;
+        header.verify_size = verify_size;
+        let pos = writer.stream_position()?;
+
+        output.seek(std::io::SeekFrom::Start(0))?;
         unsafe {
-            writer.write(any_as_u8_slice(&header)).unwrap();
+            output.write_all(any_as_u8_slice(&header.convert(self.endian)))?;
         }
-        writer.seek(std::io::SeekFrom::Start(pos)).unwrap();
-        writer.flush().unwrap();
+        output.seek(std::io::SeekFrom::Start(pos))?;
+        output.flush()?;
+        Ok(())
     }
 }
 
-#[allow(unused)]
+/// Header fields of a loaded map, for inspection tools.
+#[derive(Debug, Clone)]
+pub struct MapInfo {
+    pub version: u8,
+    pub endian: Endian,
+    pub verification: KeyVerification,
+    pub hasher_id: u32,
+    pub index_id: u32,
+    pub value_id: u32,
+    pub key_count: u64,
+    pub header_size: u64,
+    pub index_size: u64,
+    pub value_size: u64,
+    pub value_index_size: u64,
+    pub verify_size: u64,
+    pub file_size: u64,
+}
+
 struct PerfectHashMapDeserializerInner {
-    file: File,
-    mmap: memmap2::Mmap,
+    data: Data,
     header: PerfectHashMapHeader,
 }
 
@@ -207,10 +520,12 @@ where
     I: PHashIndexDeserializer<K, H>,
     V: PHashValueDeserializer,
     H: Hasher,
-    K: Hash,
+    K: PHashKey + ?Sized,
 {
     index_deserializer: I,
     value_deserializer: V,
+    verifier: KeyVerifier,
+    verify_on_load: bool,
     _pd0: PhantomData<H>,
     _pd1: PhantomData<K>,
     inner: Option<PerfectHashMapDeserializerInner>,
@@ -221,50 +536,167 @@ where
     I: PHashIndexDeserializer<K, H>,
     V: PHashValueDeserializer,
     H: Hasher,
-    K: Hash,
+    K: PHashKey + ?Sized,
 {
     pub fn new(index_deserializer: I, value_deserializer: V) -> Self {
         Self {
             index_deserializer,
             value_deserializer,
+            verifier: KeyVerifier::new(),
+            verify_on_load: false,
             inner: None,
-            _pd0: PhantomData::default(),
-            _pd1: PhantomData::default(),
+            _pd0: PhantomData,
+            _pd1: PhantomData,
         }
     }
 
-    pub fn load_from_mmap_file<P>(&mut self, path: P) -> usize
+    /// Check the section checksums while loading, which reads the whole file.
+    pub fn verify_on_load(mut self, verify_on_load: bool) -> Self {
+        self.verify_on_load = verify_on_load;
+        self
+    }
+
+    /// Recompute the section checksums of the loaded file. The header is not
+    /// checksummed, only checked for consistent sizes and ids on load.
+    pub fn verify(&self) -> Result<()> {
+        match &self.inner {
+            Some(inner) => inner.header.verify_checksums(&inner.data),
+            None => Err(Error::NotLoaded),
+        }
+    }
+
+    pub fn load_from_mmap_file<P>(&mut self, path: P) -> Result<usize>
     where
         P: AsRef<std::path::Path>,
+        I: Default,
+        V: Default,
     {
-        let file = File::options().read(true).write(false).open(path).unwrap();
-        let mmap = unsafe { memmap2::MmapOptions::new().map(&file).unwrap() };
+        let file = File::options().read(true).write(false).open(path)?;
+        let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+        self.load_from_data(Data::new(mmap))
+    }
 
-        let header_len = std::mem::size_of::<PerfectHashMapHeader>();
-        let slice = &mmap[..header_len];
+    /// Load from an in-memory buffer such as `include_bytes!` output, a `Vec<u8>`, an
+    /// `Arc<[u8]>` or a `bytes::Bytes`.
+    ///
+    /// The buffer has no alignment requirement, integers are read byte-wise.
+    pub fn load_from_buffer<T>(&mut self, buffer: T) -> Result<usize>
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+        I: Default,
+        V: Default,
+    {
+        self.load_from_data(Data::new(buffer))
+    }
 
-        let mut header = PerfectHashMapHeader::default();
-        unsafe {
-            any_as_u8_mut_slice(&mut header).copy_from_slice(slice);
+    pub fn load_from_data(&mut self, data: Data) -> Result<usize>
+    where
+        I: Default,
+        V: Default,
+    {
+        let header = PerfectHashMapHeader::parse(&data)?;
+        if self.verify_on_load {
+            header.verify_checksums(&data)?;
+        }
+        if header.hasher_id != H::ID {
+            return Err(Error::TypeMismatch {
+                kind: "hasher",
+                expected: H::ID,
+                found: header.hasher_id,
+            });
+        }
+        if header.index_id != I::ID {
+            return Err(Error::TypeMismatch {
+                kind: "index",
+                expected: I::ID,
+                found: header.index_id,
+            });
+        }
+        if header.value_id != V::ID {
+            return Err(Error::TypeMismatch {
+                kind: "value",
+                expected: V::ID,
+                found: header.value_id,
+            });
         }
+        let header_len = header.header_size as usize;
+        let verification = KeyVerification::from_flag(header.flag)
+            .ok_or(Error::Corrupted("unknown key verification mode"))?;
 
-        let beg = header_len as usize;
+        // load into fresh readers, so a failure keeps the loaded file intact
+        let beg = header_len;
         let end = beg + header.index_size as usize;
+        let mut index_deserializer = I::default();
+        index_deserializer.load(data.slice(beg..end), header.endian())?;
+        let max_hash_index = index_deserializer.max_hash_index();
 
-        self.index_deserializer.load(&mmap[beg..end]);
-
-        let beg = header_len as usize + header.index_size as usize;
+        let beg = end;
+        let mid = beg + header.value_index_size as usize;
         let end = beg + header.value_size as usize;
-        self.value_deserializer.load(&mmap[beg..end]);
-        self.inner = Some(PerfectHashMapDeserializerInner { file, mmap, header });
+        let mut value_deserializer = self.value_deserializer.unloaded();
+        value_deserializer.load(data.slice(beg..mid), data.slice(mid..end), header.endian())?;
+        if value_deserializer.count() < max_hash_index as usize {
+            return Err(Error::Corrupted("value count less than index size"));
+        }
+
+        let beg = end;
+        let end = beg + header.verify_size as usize;
+        let mut verifier = KeyVerifier::new();
+        verifier.load(
+            verification,
+            data.slice(beg..end),
+            max_hash_index,
+            header.endian(),
+        )?;
 
-        end
+        self.index_deserializer = index_deserializer;
+        self.value_deserializer = value_deserializer;
+        self.verifier = verifier;
+        self.inner = Some(PerfectHashMapDeserializerInner { data, header });
+
+        Ok(end)
     }
 
-    pub fn get<'a>(&'a self, key: &K) -> &'a [u8] {
+    /// Look up the value of `key`.
+    ///
+    /// Returns `None` if the map was written with a [`KeyVerification`] mode and `key`
+    /// is not one of its keys, or if the index hashes `key` past the slots, as it does
+    /// for empty maps and corrupted files; otherwise absent keys yield an arbitrary
+    /// value.
+    pub fn get(&self, key: &K) -> Option<V::Value<'_>> {
         let hash_index = self.index_deserializer.get_hash_index(key);
-        let value = self.value_deserializer.get(hash_index);
-        value
+        if hash_index >= self.index_deserializer.max_hash_index() {
+            return None;
+        }
+        if !self.verifier.verify::<K, H>(hash_index, key) {
+            return None;
+        }
+        Some(self.value_deserializer.get(hash_index))
+    }
+
+    /// Header fields of the loaded file.
+    pub fn info(&self) -> Option<MapInfo> {
+        let inner = self.inner.as_ref()?;
+        Some(inner.header.info(inner.data.len()))
+    }
+
+    pub fn index_stats(&self) -> IndexStats {
+        self.index_deserializer.stats()
+    }
+
+    /// Number of slots, which bounds the hash indexes. Slots without a key hold an
+    /// empty value.
+    pub fn slot_count(&self) -> usize {
+        self.index_deserializer.max_hash_index() as usize
+    }
+
+    /// The key and value of slot `index`. The key is only known for maps written with
+    /// [`KeyVerification::FullKey`]. `None` if `index` is not below [`Self::slot_count`].
+    pub fn slot(&self, index: HashIndex) -> Option<(Option<&[u8]>, V::Value<'_>)> {
+        if index >= self.index_deserializer.max_hash_index() {
+            return None;
+        }
+        Some((self.verifier.key(index), self.value_deserializer.get(index)))
     }
 }
 
@@ -310,21 +742,1335 @@ mod tests {
                 .iter()
                 .map(|v| (v.0.as_str(), v.1.as_bytes()))
                 .collect();
-            serializer.write_to_file(&tmp_keys, test_file);
+            serializer.write_to_file(&tmp_keys, test_file).unwrap();
         }
         {
             let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, _, _, _>::new(
                 CHDReader::new(),
                 DefaultHashValueReader::new(),
             );
-            deserializer.load_from_mmap_file(test_file);
+            deserializer.load_from_mmap_file(test_file).unwrap();
             for (k, v) in keys {
                 unsafe {
-                    let value: &str = std::str::from_utf8_unchecked(deserializer.get(&k));
+                    let value: &str = std::str::from_utf8_unchecked(deserializer.get(&k).unwrap());
                     assert_eq!(value, v);
                 }
             }
         }
         std::fs::remove_file(test_file).unwrap();
     }
+
+    #[test]
+    fn verification_test() {
+        let keys: Vec<String> = (0..1024).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+
+        for (verification, test_file) in [
+            (KeyVerification::FullKey, "./test_full_key.bin"),
+            (KeyVerification::Fingerprint(4), "./test_fingerprint.bin"),
+        ] {
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::new(),
+                DefaultHashValueWriter::new(),
+            )
+            .verification(verification);
+            serializer.write_to_file(&kvs, test_file).unwrap();
+
+            let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, _, _, _>::new(
+                CHDReader::new(),
+                DefaultHashValueReader::new(),
+            );
+            deserializer.load_from_mmap_file(test_file).unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(deserializer.get(k), Some(*v));
+            }
+            let absent = (0..1024)
+                .filter(|i| {
+                    deserializer
+                        .get(&format!("absent-{}", i).as_str())
+                        .is_some()
+                })
+                .count();
+            if verification == KeyVerification::FullKey {
+                assert_eq!(absent, 0);
+            } else {
+                assert!(absent <= 1);
+            }
+            std::fs::remove_file(test_file).unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_key_test() {
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("b", b"2"), ("c", b"3")];
+        let mut unused = 0;
+        for seed in 0..16 {
+            // a sparse table, so the empty key often lands on an unused slot
+            let config = CHDGeneratorConfig::default().load_factor(0.5).seed(seed);
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::from_config(config),
+                DefaultHashValueWriter::new(),
+            )
+            .verification(KeyVerification::FullKey);
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            serializer.write_to(&kvs, &mut cursor).unwrap();
+
+            let mut map = PerfectHashMapDeserializer::<hasher::CityHash, str, _, _>::new(
+                CHDReader::new(),
+                DefaultHashValueReader::new(),
+            );
+            map.load_from_buffer(cursor.into_inner()).unwrap();
+            assert_eq!(map.get(""), None);
+            let index = map.index_deserializer.get_hash_index("");
+            if map.slot(index).unwrap().0.is_none() {
+                unused += 1;
+            }
+        }
+        assert!(unused > 0);
+
+        let kvs: Vec<(&str, &[u8])> = vec![("", b"empty"), ("a", b"1")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(0.5)),
+            DefaultHashValueWriter::new(),
+        )
+        .verification(KeyVerification::FullKey);
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+        let mut map = PerfectHashMapDeserializer::<hasher::CityHash, str, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        map.load_from_buffer(cursor.into_inner()).unwrap();
+        assert_eq!(map.get(""), Some(&b"empty"[..]));
+    }
+
+    #[test]
+    fn error_test() {
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("b", b"2")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(2.0)),
+            DefaultHashValueWriter::new(),
+        );
+        let result = serializer.write_to(&kvs, std::io::Cursor::new(Vec::new()));
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, &str, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        let result = deserializer.load_from_mmap_file("./not_exist.bin");
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn corrupted_test() {
+        let test_file = "./test_corrupted.bin";
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("b", b"2"), ("c", b"3")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+        let data = cursor.into_inner();
+
+        let load = |data: &[u8]| {
+            std::fs::write(test_file, data).unwrap();
+            let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, &str, _, _>::new(
+                CHDReader::new(),
+                DefaultHashValueReader::new(),
+            );
+            deserializer.load_from_mmap_file(test_file).map(|_| ())
+        };
+
+        assert!(load(&data).is_ok());
+        assert!(matches!(load(&data[..10]), Err(Error::Corrupted(_))));
+        assert!(matches!(
+            load(&data[..data.len() - 1]),
+            Err(Error::Corrupted(_))
+        ));
+
+        let mut foreign = data.clone();
+        foreign[0] ^= 0xFF;
+        assert!(matches!(load(&foreign), Err(Error::Corrupted(_))));
+
+        let mut newer = data.clone();
+        newer[5] = VERSION + 1;
+        assert!(matches!(load(&newer), Err(Error::VersionMismatch { .. })));
+
+        // zero the chd table size
+        let header_len = std::mem::size_of::<PerfectHashMapHeader>();
+        let mut broken_index = data.clone();
+        broken_index[header_len + 4..header_len + 8].fill(0);
+        assert!(matches!(load(&broken_index), Err(Error::Corrupted(_))));
+
+        std::fs::remove_file(test_file).unwrap();
+    }
+
+    #[test]
+    fn checksum_test() {
+        let test_file = "./test_checksum.bin";
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("b", b"2"), ("c", b"3")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        )
+        .verification(KeyVerification::FullKey);
+        serializer.write_to_file(&kvs, test_file).unwrap();
+        let mut data = std::fs::read(test_file).unwrap();
+
+        let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, &str, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        )
+        .verify_on_load(true);
+        assert!(matches!(deserializer.verify(), Err(Error::NotLoaded)));
+        deserializer.load_from_mmap_file(test_file).unwrap();
+        deserializer.verify().unwrap();
+
+        // flip a byte of the value payload
+        let payload = data.iter().rposition(|v| *v == b'3').unwrap();
+        data[payload] = b'4';
+        std::fs::write(test_file, &data).unwrap();
+        let result = deserializer.load_from_mmap_file(test_file);
+        assert!(matches!(
+            result,
+            Err(Error::Corrupted("value payload checksum mismatch"))
+        ));
+
+        std::fs::remove_file(test_file).unwrap();
+    }
+
+    #[test]
+    fn endian_test() {
+        let keys: Vec<String> = (0..1024).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+        let foreign = match Endian::NATIVE {
+            Endian::Little => Endian::Big,
+            Endian::Big => Endian::Little,
+        };
+
+        for (endian, test_file) in [
+            (Endian::NATIVE, "./test_native_endian.bin"),
+            (foreign, "./test_foreign_endian.bin"),
+        ] {
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::new(),
+                DefaultHashValueWriter::new(),
+            )
+            .verification(KeyVerification::FullKey)
+            .endian(endian);
+            serializer.write_to_file(&kvs, test_file).unwrap();
+
+            let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, _, _, _>::new(
+                CHDReader::new(),
+                DefaultHashValueReader::new(),
+            )
+            .verify_on_load(true);
+            deserializer.load_from_mmap_file(test_file).unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(deserializer.get(k), Some(*v));
+            }
+            assert_eq!(deserializer.get(&"absent"), None);
+        }
+
+        let native = std::fs::read("./test_native_endian.bin").unwrap();
+        let swapped = std::fs::read("./test_foreign_endian.bin").unwrap();
+        assert_eq!(native.len(), swapped.len());
+        assert_ne!(native, swapped);
+
+        std::fs::remove_file("./test_native_endian.bin").unwrap();
+        std::fs::remove_file("./test_foreign_endian.bin").unwrap();
+    }
+
+    #[test]
+    fn shared_test() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let test_file = "./test_shared.bin";
+        let keys: Vec<String> = (0..1024).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        serializer.write_to_file(&kvs, test_file).unwrap();
+
+        let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, String, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        deserializer.load_from_mmap_file(test_file).unwrap();
+        // the mapping is shared by the readers, the file is not needed anymore
+        std::fs::remove_file(test_file).unwrap();
+        assert_send_sync(&deserializer);
+
+        let deserializer = std::sync::Arc::new(deserializer);
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let deserializer = deserializer.clone();
+                let keys = keys.clone();
+                std::thread::spawn(move || {
+                    for k in keys.iter().skip(t).step_by(4) {
+                        assert_eq!(deserializer.get(k), Some(k.as_bytes()));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn buffer_test() {
+        let keys: Vec<String> = (0..1024).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+        let buffer = cursor.into_inner();
+
+        // an unaligned copy of the file inside a larger buffer
+        let mut shifted = vec![0u8];
+        shifted.extend_from_slice(&buffer);
+        let shifted = Data::new(shifted);
+
+        let static_buffer: &'static [u8] = Box::leak(buffer.clone().into_boxed_slice());
+        let datas = [
+            Data::new(buffer.clone()),
+            Data::new(static_buffer),
+            Data::new(std::sync::Arc::<[u8]>::from(buffer.as_slice())),
+            shifted.slice(1..shifted.len()),
+        ];
+        for data in datas {
+            let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, _, _, _>::new(
+                CHDReader::new(),
+                DefaultHashValueReader::new(),
+            );
+            deserializer.load_from_data(data).unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(deserializer.get(k), Some(*v));
+            }
+        }
+
+        let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, &str, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        assert_eq!(
+            deserializer.load_from_buffer(buffer.clone()).unwrap(),
+            buffer.len()
+        );
+    }
+
+    #[test]
+    fn key_test() {
+        assert_eq!(1u32.as_key_bytes().as_ref(), &[1, 0, 0, 0]);
+        assert_eq!(1usize.as_key_bytes(), 1u64.as_key_bytes());
+        assert_eq!((-1isize).as_key_bytes(), (-1i64).as_key_bytes());
+        assert_eq!("ab".as_key_bytes(), String::from("ab").as_key_bytes());
+        assert_eq!("ab".as_key_bytes(), b"ab".as_key_bytes());
+        assert_ne!(("ab", "c").as_key_bytes(), ("a", "bc").as_key_bytes());
+        assert_eq!((1u8, "c").as_key_bytes(), [1, 0, 0, 0, 1, b'c']);
+
+        let kvs: Vec<((u64, &str), &[u8])> = (0..1024u64)
+            .map(|i| ((i, if i % 2 == 0 { "even" } else { "odd" }), &b"value"[..]))
+            .collect();
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        )
+        .verification(KeyVerification::FullKey);
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+
+        let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, _, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        deserializer.load_from_buffer(cursor.into_inner()).unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(deserializer.get(k), Some(*v));
+        }
+        assert_eq!(deserializer.get(&(0, "odd")), None);
+    }
+
+    #[test]
+    fn unsized_key_test() {
+        let keys: Vec<String> = (0..1024).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&String, &[u8])> = keys.iter().map(|k| (k, k.as_bytes())).collect();
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+
+        let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, str, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        deserializer.load_from_buffer(cursor.into_inner()).unwrap();
+        for k in &keys {
+            assert_eq!(deserializer.get(k.as_str()), Some(k.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn hasher_test() {
+        use std::hash::Hasher as _;
+
+        let mut streamed = hasher::CityHash::default();
+        let mut single = hasher::CityHash::default();
+        let long = [7u8; 100];
+        for part in [&b"key"[..], &long[..], &b"-suffix"[..]] {
+            streamed.write(part);
+        }
+        single.write(&[&b"key"[..], &long[..], &b"-suffix"[..]].concat());
+        assert_eq!(streamed.finish_u128(), single.finish_u128());
+    }
+
+    #[test]
+    fn seed_test() {
+        use std::hash::Hasher as _;
+
+        let mut seeded = hasher::CityHash::with_seed(0);
+        let mut default = hasher::CityHash::default();
+        seeded.write(b"key");
+        default.write(b"key");
+        assert_eq!(seeded.finish_u128(), default.finish_u128());
+
+        let keys: Vec<u32> = (0..500).collect();
+        let kvs: Vec<(u32, &[u8])> = keys.iter().map(|k| (*k, &b"value"[..])).collect();
+        let build = |seed: u64| {
+            let config = CHDGeneratorConfig::default()
+                .minimal(true)
+                .seed(seed)
+                .seed_retry(64);
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::from_config(config),
+                DefaultHashValueWriter::new(),
+            );
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            serializer.write_to(&kvs, &mut cursor).unwrap();
+            cursor.into_inner()
+        };
+        let a = build(0);
+        let b = build(42);
+        assert_ne!(a, b);
+
+        for data in [a, b] {
+            let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, _, _, _>::new(
+                CHDReader::new(),
+                DefaultHashValueReader::new(),
+            );
+            deserializer.load_from_buffer(data).unwrap();
+            let mut seen = HashSet::new();
+            for k in &keys {
+                assert_eq!(deserializer.get(k), Some(&b"value"[..]));
+                assert!(
+                    seen.insert(<CHDReader<hasher::CityHash> as PHashIndexDeserializer<
+                        u32,
+                        _,
+                    >>::get_hash_index(
+                        &deserializer.index_deserializer, k
+                    ))
+                );
+            }
+        }
+
+        let config = CHDGeneratorConfig::default().seed_retry(0);
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::from_config(config),
+            DefaultHashValueWriter::new(),
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    /// Write `kvs` with `generator` in `endian` and check that the typed and the any
+    /// reader find every key, returning the loaded map for algorithm specific checks.
+    fn roundtrip<'a, I>(
+        generator: I,
+        kvs: &[(&'a str, &[u8])],
+        endian: Endian,
+    ) -> PerfectHashMapDeserializer<hasher::CityHash, str, I::Deserializer, DefaultHashValueReader>
+    where
+        I: PHashIndexSerializer<&'a str, hasher::CityHash>,
+        I::Deserializer: PHashIndexDeserializer<str, hasher::CityHash> + Default,
+    {
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            generator,
+            DefaultHashValueWriter::new(),
+        )
+        .verification(KeyVerification::Fingerprint(4))
+        .endian(endian);
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(kvs, &mut cursor).unwrap();
+        let data = cursor.into_inner();
+
+        let mut map = PerfectHashMapDeserializer::new(
+            I::Deserializer::default(),
+            DefaultHashValueReader::new(),
+        );
+        map.load_from_buffer(data.clone()).unwrap();
+        map.verify().unwrap();
+        for (k, v) in kvs {
+            assert_eq!(map.get(k), Some(*v));
+        }
+        assert_eq!(map.get("absent"), None);
+
+        let any = AnyPerfectHashMap::load_from_buffer(data).unwrap();
+        for (k, v) in kvs {
+            assert_eq!(any.get(k.as_bytes()).as_deref(), Some(*v));
+        }
+        map
+    }
+
+    #[test]
+    fn displacement_test() {
+        use chd::DisplacementEncoding;
+
+        let keys: Vec<String> = (0..20000).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+        let mut index_sizes = Vec::new();
+        for displacement in [
+            DisplacementEncoding::Plain,
+            DisplacementEncoding::Compact,
+            DisplacementEncoding::Dictionary,
+        ] {
+            for endian in [Endian::Little, Endian::Big] {
+                let config = CHDGeneratorConfig::default()
+                    .minimal(true)
+                    .displacement(displacement);
+                let map = roundtrip(CHDGenerator::from_config(config), &kvs, endian);
+                if endian == Endian::Little {
+                    index_sizes.push(map.info().unwrap().index_size);
+                }
+            }
+        }
+        // most displacements are small, so both encodings are well below 32 bits each
+        assert!(index_sizes[1] * 3 / 2 < index_sizes[0]);
+        assert!(index_sizes[2] * 3 / 2 < index_sizes[0]);
+    }
+
+    #[test]
+    fn chd_table_size_test() {
+        // table_size * table_size no longer fits in u32
+        let keys: Vec<u32> = (0..70000).collect();
+        let kvs: Vec<(u32, &[u8])> = keys.iter().map(|k| (*k, &b"value"[..])).collect();
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+        let mut map = PerfectHashMapDeserializer::<hasher::CityHash, u32, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        map.load_from_buffer(cursor.into_inner()).unwrap();
+        for k in &keys {
+            assert_eq!(map.get(k), Some(&b"value"[..]));
+        }
+
+        let kvs: Vec<(u32, &[u8])> = Vec::new();
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn failed_reload_test() {
+        let build = |prefix: &str| {
+            let keys: Vec<String> = (0..100).map(|i| format!("{}-{}", prefix, i)).collect();
+            let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::new(),
+                DefaultHashValueWriter::new(),
+            )
+            .verification(KeyVerification::FullKey)
+            .endian(Endian::Little);
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            serializer.write_to(&kvs, &mut cursor).unwrap();
+            cursor.into_inner()
+        };
+        let old = build("old");
+        let mut new = build("new");
+
+        let mut map = PerfectHashMapDeserializer::<hasher::CityHash, str, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        map.load_from_buffer(new.clone()).unwrap();
+        let info = map.info().unwrap();
+        // an unknown offset width in the value section, after the index loaded fine
+        new[(info.header_size + info.index_size) as usize + 7] = 3;
+
+        map.load_from_buffer(old.clone()).unwrap();
+        assert!(matches!(
+            map.load_from_buffer(new),
+            Err(Error::Corrupted(_))
+        ));
+        assert_eq!(map.info().unwrap().file_size, old.len() as u64);
+        for i in 0..100 {
+            let key = format!("old-{}", i);
+            assert_eq!(map.get(&key), Some(key.as_bytes()));
+        }
+        assert_eq!(map.get("new-0"), None);
+    }
+
+    #[test]
+    fn hasher_select_test() {
+        fn roundtrip<H: Hasher>() -> Vec<u8> {
+            let kvs: Vec<(u64, &[u8])> = (0..2048u64).map(|i| (i * 3, &b"value"[..])).collect();
+            let mut serializer = PerfectHashMapSerializer::<H, _, _, _>::new(
+                CHDGenerator::new(),
+                DefaultHashValueWriter::new(),
+            )
+            .verification(KeyVerification::Fingerprint(4));
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            serializer.write_to(&kvs, &mut cursor).unwrap();
+            let data = cursor.into_inner();
+
+            let mut deserializer = PerfectHashMapDeserializer::<H, u64, _, _>::new(
+                CHDReader::new(),
+                DefaultHashValueReader::new(),
+            );
+            deserializer.load_from_buffer(data.clone()).unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(deserializer.get(k), Some(*v));
+                assert_eq!(deserializer.get(&(k + 1)), None);
+            }
+            data
+        }
+
+        roundtrip::<hasher::CityHash>();
+        roundtrip::<hasher::WyHash>();
+        roundtrip::<hasher::IntHash>();
+        let data = roundtrip::<hasher::Xxh3>();
+
+        let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, u64, _, _>::new(
+            CHDReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        assert!(matches!(
+            deserializer.load_from_buffer(data),
+            Err(Error::TypeMismatch {
+                kind: "hasher",
+                expected: 0,
+                found: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn type_id_test() {
+        let kvs: Vec<(u32, &[u8])> = (0..64u32).map(|i| (i, &b"value"[..])).collect();
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+        let data = cursor.into_inner();
+
+        let id_offset = std::mem::offset_of!(PerfectHashMapHeader, index_id);
+        for (offset, kind) in [(id_offset, "index"), (id_offset + 4, "value")] {
+            let mut other = data.clone();
+            other[offset..offset + 4].copy_from_slice(&7u32.to_ne_bytes());
+            let mut deserializer = PerfectHashMapDeserializer::<hasher::CityHash, u32, _, _>::new(
+                CHDReader::new(),
+                DefaultHashValueReader::new(),
+            );
+            match deserializer.load_from_buffer(other) {
+                Err(Error::TypeMismatch {
+                    kind: found_kind,
+                    expected: 0,
+                    found: 7,
+                }) => assert_eq!(found_kind, kind),
+                _ => panic!("{} id not checked", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn any_map_test() {
+        let kvs: Vec<(u64, &[u8])> = (0..512u64).map(|i| (i, &b"int"[..])).collect();
+        let mut serializer = PerfectHashMapSerializer::<hasher::Xxh3, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        )
+        .verification(KeyVerification::Fingerprint(8));
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+        let data = cursor.into_inner();
+
+        let map = AnyPerfectHashMap::load_from_buffer(data.clone()).unwrap();
+        map.verify().unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(map.get_key(k).as_deref(), Some(*v));
+            assert_eq!(map.get(&k.to_le_bytes()).as_deref(), Some(*v));
+        }
+        assert_eq!(map.get_key(&1000u64), None);
+
+        let test_file = "./test_any.bin";
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("bb", b"2"), ("ccc", b"3")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            DefaultHashValueWriter::new(),
+        )
+        .verification(KeyVerification::FullKey);
+        serializer.write_to_file(&kvs, test_file).unwrap();
+        let map = AnyPerfectHashMap::load_from_mmap_file(test_file).unwrap();
+        std::fs::remove_file(test_file).unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(map.get(k.as_bytes()).as_deref(), Some(*v));
+        }
+        assert_eq!(map.get(b"d"), None);
+
+        let mut unknown = data;
+        unknown[12..16].copy_from_slice(&99u32.to_ne_bytes());
+        assert!(matches!(
+            AnyPerfectHashMap::load_from_buffer(unknown),
+            Err(Error::UnknownType {
+                kind: "hasher",
+                id: 99
+            })
+        ));
+    }
+
+    #[test]
+    fn inspect_test() {
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("bb", b"2"), ("ccc", b"3")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(0.5)),
+            DefaultHashValueWriter::new(),
+        )
+        .verification(KeyVerification::FullKey);
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+        let map = AnyPerfectHashMap::load_from_buffer(cursor.into_inner()).unwrap();
+
+        let info = map.info();
+        assert_eq!(info.version, VERSION);
+        assert_eq!(info.key_count, 3);
+        assert_eq!(info.verification, KeyVerification::FullKey);
+        assert_eq!(
+            info.header_size + info.index_size + info.value_size + info.verify_size,
+            info.file_size
+        );
+
+        let stats = map.index_stats();
+        assert_eq!(stats.max_hash_index as usize, map.slot_count());
+        assert!(stats.fields.iter().any(|(name, _)| *name == "bucket_size"));
+        let buckets: u64 = stats.histogram.iter().map(|(_, count)| count).sum();
+        assert_eq!(buckets, 1);
+
+        let mut slots: Vec<(&[u8], Cow<[u8]>)> = (0..map.slot_count() as u32)
+            .map(|index| map.slot(index).unwrap())
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(key, value)| (key.unwrap(), value))
+            .collect();
+        slots.sort();
+        let expected: Vec<(&[u8], Cow<[u8]>)> = kvs
+            .iter()
+            .map(|(k, v)| (k.as_bytes(), Cow::Borrowed(*v)))
+            .collect();
+        assert_eq!(slots, expected);
+        assert!(map.slot(map.slot_count() as u32).is_none());
+    }
+
+    #[test]
+    fn recsplit_test() {
+        use recsplit::*;
+
+        let keys: Vec<String> = (0..20000).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+        for (leaf_size, bucket_size) in [(8, 100), (5, 10), (1, 7), (10, 2000)] {
+            let config = RecSplitGeneratorConfig::default()
+                .leaf_size(leaf_size)
+                .bucket_size(bucket_size);
+            for endian in [Endian::Little, Endian::Big] {
+                let map = roundtrip(RecSplitGenerator::from_config(config.clone()), &kvs, endian);
+                assert_eq!(map.slot_count(), kvs.len());
+
+                let stats = map.index_stats();
+                let code_bits = stats.fields.iter().find(|(name, _)| *name == "code_bits");
+                if leaf_size == 8 && bucket_size == 100 {
+                    // about 1.8 bits per key, plus the bucket positions
+                    assert!(code_bits.unwrap().1 < 2 * keys.len() as u64);
+                    assert!(map.info().unwrap().index_size < keys.len() as u64 * 3 / 8);
+                }
+            }
+        }
+
+        // a rice parameter too large to shift by fails the load
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            RecSplitGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+        let mut data = cursor.into_inner();
+        let mut map = PerfectHashMapDeserializer::<hasher::CityHash, str, _, _>::new(
+            RecSplitReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        map.load_from_buffer(data.clone()).unwrap();
+        // the rice parameters follow the 48 byte recsplit header, from nodes of 0 keys
+        let rice = map.info().unwrap().header_size as usize + 48;
+        data[rice + 2] = 64;
+        assert!(matches!(
+            map.load_from_buffer(data),
+            Err(Error::Corrupted("invalid recsplit rice parameter"))
+        ));
+
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("a", b"2")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            RecSplitGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::ConstructionFailed)
+        ));
+    }
+
+    #[test]
+    fn bbhash_test() {
+        use bbhash::*;
+
+        let keys: Vec<String> = (0..20000).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+        for gamma in [1.0, 2.0, 5.0] {
+            let config = BBHashGeneratorConfig::default().gamma(gamma);
+            for endian in [Endian::Little, Endian::Big] {
+                let map = roundtrip(BBHashGenerator::from_config(config.clone()), &kvs, endian);
+                assert_eq!(map.slot_count(), kvs.len());
+
+                // every key is counted at exactly one level
+                let stats = map.index_stats();
+                let placed: u64 = stats.histogram.iter().map(|(_, keys)| keys).sum();
+                assert_eq!(placed, keys.len() as u64);
+            }
+        }
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            BBHashGenerator::from_config(BBHashGeneratorConfig::default().gamma(0.5)),
+            DefaultHashValueWriter::new(),
+        );
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("a", b"2")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            BBHashGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::ConstructionFailed)
+        ));
+    }
+
+    #[test]
+    fn empty_map_test() {
+        use bbhash::*;
+
+        let kvs: Vec<(&str, &[u8])> = Vec::new();
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            BBHashGenerator::new(),
+            DefaultHashValueWriter::new(),
+        )
+        .verification(KeyVerification::None);
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        serializer.write_to(&kvs, &mut cursor).unwrap();
+
+        let mut map = PerfectHashMapDeserializer::<hasher::CityHash, str, _, _>::new(
+            BBHashReader::new(),
+            DefaultHashValueReader::new(),
+        );
+        map.load_from_buffer(cursor.into_inner()).unwrap();
+        assert_eq!(map.slot_count(), 0);
+        assert!(map.slot(0).is_none());
+        assert_eq!(map.get("absent"), None);
+    }
+
+    #[test]
+    fn pthash_test() {
+        use pthash::*;
+
+        let keys: Vec<String> = (0..20000).map(|i| format!("key-{}", i)).collect();
+        let kvs: Vec<(&str, &[u8])> = keys.iter().map(|k| (k.as_str(), k.as_bytes())).collect();
+        for encoding in [
+            PilotEncoding::Compact,
+            PilotEncoding::Dictionary,
+            PilotEncoding::EliasFano,
+        ] {
+            for (minimal, load_factor) in [(true, 0.99), (true, 1.0), (false, 0.9)] {
+                let config = PTHashGeneratorConfig::default()
+                    .encoding(encoding)
+                    .minimal(minimal)
+                    .load_factor(load_factor);
+                for endian in [Endian::Little, Endian::Big] {
+                    let map = roundtrip(PTHashGenerator::from_config(config.clone()), &kvs, endian);
+                    if minimal {
+                        assert_eq!(map.slot_count(), kvs.len());
+                    } else {
+                        assert!(map.slot_count() > kvs.len());
+                    }
+                }
+            }
+        }
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            PTHashGenerator::from_config(PTHashGeneratorConfig::default().load_factor(0.1)),
+            DefaultHashValueWriter::new(),
+        );
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let kvs: Vec<(&str, &[u8])> = vec![("a", b"1"), ("a", b"2")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            PTHashGenerator::new(),
+            DefaultHashValueWriter::new(),
+        );
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::ConstructionFailed)
+        ));
+    }
+
+    #[test]
+    fn wide_offset_test() {
+        // only the offsets are written, the values themselves would not fit in memory
+        let chunk = vec![0u8; 1 << 20];
+        let values: Vec<&[u8]> = vec![&chunk; 5000];
+        let mut index = Vec::new();
+        DefaultHashValueWriter::new()
+            .write_index(&values, &mut index, Endian::NATIVE)
+            .unwrap();
+        assert_eq!(index.len(), 8 + 5000 * 8);
+        let last = u64::from_ne_bytes(index[index.len() - 8..].try_into().unwrap());
+        assert_eq!(last, 5000 << 20);
+
+        let mut reader = DefaultHashValueReader::new();
+        reader
+            .load(Data::new(index.clone()), Data::default(), Endian::NATIVE)
+            .unwrap();
+        assert_eq!(reader.count(), 5000);
+        assert_eq!(reader.offset_width(), 8);
+
+        index[7] = 3;
+        assert!(matches!(
+            reader.load(Data::new(index), Data::default(), Endian::NATIVE),
+            Err(Error::Corrupted(_))
+        ));
+
+        let values: Vec<&[u8]> = vec![&chunk; 3];
+        let mut index = Vec::new();
+        DefaultHashValueWriter::new()
+            .write_index(&values, &mut index, Endian::Big)
+            .unwrap();
+        assert_eq!(index.len(), 8 + 3 * 4);
+        reader
+            .load(Data::new(index), Data::default(), Endian::Big)
+            .unwrap();
+        assert_eq!(reader.count(), 3);
+        assert_eq!(reader.offset_width(), 4);
+    }
+
+    #[test]
+    fn elias_fano_test() {
+        let mut rng = rand::thread_rng();
+        let mut values = vec![0u64];
+        for _ in 0..3000 {
+            let gap = match rng.gen_range(0..4) {
+                0 => 0,
+                1 => rng.gen_range(0..8),
+                2 => rng.gen_range(0..1000),
+                _ => rng.gen_range(0..1 << 40),
+            };
+            values.push(values.last().unwrap() + gap);
+        }
+        for endian in [Endian::Little, Endian::Big] {
+            let mut buffer = Vec::new();
+            elias_fano::write(&values, &mut buffer, endian).unwrap();
+            let (ef, len) =
+                elias_fano::EliasFano::load(&Data::new(buffer.clone()), endian).unwrap();
+            assert_eq!(len, buffer.len());
+            assert_eq!(ef.len(), values.len());
+            for i in 0..values.len() - 1 {
+                assert_eq!(ef.get_pair(i), (values[i], values[i + 1]));
+            }
+        }
+
+        let test_file = "./test_elias_fano.bin";
+        let values: Vec<String> = (0..2000)
+            .map(|i| "v".repeat(i % 5 + if i % 97 == 0 { 300 } else { 0 }))
+            .collect();
+        let kvs: Vec<(u32, &[u8])> = (0..2000u32)
+            .map(|i| (i, values[i as usize].as_bytes()))
+            .collect();
+        for endian in [Endian::Little, Endian::Big] {
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(0.5)),
+                EliasFanoValueWriter::new(),
+            )
+            .verification(KeyVerification::Fingerprint(4))
+            .endian(endian);
+            serializer.write_to_file(&kvs, test_file).unwrap();
+
+            let mut map = PerfectHashMapDeserializer::<hasher::CityHash, u32, _, _>::new(
+                CHDReader::new(),
+                EliasFanoValueReader::new(),
+            );
+            map.load_from_mmap_file(test_file).unwrap();
+            map.verify().unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(map.get(k), Some(*v));
+            }
+            assert_eq!(map.get(&5000), None);
+
+            let any = AnyPerfectHashMap::load_from_mmap_file(test_file).unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(any.get_key(k).as_deref(), Some(*v));
+            }
+        }
+        std::fs::remove_file(test_file).unwrap();
+    }
+
+    #[test]
+    fn dedup_test() {
+        let categories = ["fruit", "vegetable", "grain", ""];
+        let kvs: Vec<(u32, &[u8])> = (0..1001u32)
+            .map(|i| (i, categories[i as usize % 4].as_bytes()))
+            .collect();
+        for endian in [Endian::Little, Endian::Big] {
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(0.5)),
+                DedupValueWriter::new(),
+            )
+            .endian(endian);
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            serializer.write_to(&kvs, &mut cursor).unwrap();
+            let values: Vec<&[u8]> = kvs.iter().map(|(_, v)| *v).collect();
+            let stats = DedupStats::new(&values);
+            assert_eq!(stats.values, 751);
+            assert_eq!(stats.distinct, 3);
+            assert_eq!(stats.total_bytes, 251 * 5 + 250 * 9 + 250 * 5);
+            assert_eq!(stats.stored_bytes, 5 + 9 + 5);
+            assert_eq!(stats.saved_bytes(), stats.total_bytes - 19);
+
+            let data = cursor.into_inner();
+            let mut map = PerfectHashMapDeserializer::<hasher::CityHash, u32, _, _>::new(
+                CHDReader::new(),
+                DedupValueReader::new(),
+            );
+            map.load_from_buffer(data.clone()).unwrap();
+            map.verify().unwrap();
+            assert_eq!(map.value_deserializer.distinct_count(), 3);
+            for (k, v) in &kvs {
+                assert_eq!(map.get(k), Some(*v));
+            }
+
+            let any = AnyPerfectHashMap::load_from_buffer(data).unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(any.get_key(k).as_deref(), Some(*v));
+            }
+        }
+    }
+
+    #[test]
+    fn block_compressed_test() {
+        let values: Vec<String> = (0..3000)
+            .map(|i| format!("{{\"id\": {}, \"name\": \"item\"}}", i).repeat(i % 3))
+            .collect();
+        let kvs: Vec<(u32, &[u8])> = (0..3000u32)
+            .map(|i| (i, values[i as usize].as_bytes()))
+            .collect();
+        let raw: usize = values.iter().map(|v| v.len()).sum();
+        for endian in [Endian::Little, Endian::Big] {
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(0.8)),
+                BlockCompressedValueWriter::new().block_slots(32),
+            )
+            .verification(KeyVerification::Fingerprint(4))
+            .endian(endian);
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            serializer.write_to(&kvs, &mut cursor).unwrap();
+            let data = cursor.into_inner();
+
+            for cache in [0, 4] {
+                let mut map = PerfectHashMapDeserializer::<hasher::CityHash, u32, _, _>::new(
+                    CHDReader::new(),
+                    BlockCompressedValueReader::new().cache_blocks(cache),
+                );
+                map.load_from_buffer(data.clone()).unwrap();
+                map.verify().unwrap();
+                assert!((map.info().unwrap().value_size as usize) < raw / 2);
+                for (k, v) in &kvs {
+                    assert_eq!(map.get(k).as_deref(), Some(*v));
+                }
+                assert!(map.get(&5000).is_none());
+            }
+
+            // a size prefix beyond the largest block fails the load
+            let mut corrupted = data.clone();
+            let mut map = PerfectHashMapDeserializer::<hasher::CityHash, u32, _, _>::new(
+                CHDReader::new(),
+                BlockCompressedValueReader::new(),
+            );
+            map.load_from_buffer(data.clone()).unwrap();
+            let info = map.info().unwrap();
+            let payload = (info.header_size + info.index_size + info.value_index_size) as usize;
+            corrupted[payload..payload + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(matches!(
+                map.load_from_buffer(corrupted),
+                Err(Error::Corrupted(_))
+            ));
+
+            let any = AnyPerfectHashMap::load_from_buffer(data).unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(any.get_key(k).as_deref(), Some(*v));
+            }
+        }
+    }
+
+    #[test]
+    fn dictionary_test() {
+        let mut rng = rand::thread_rng();
+        let values: Vec<String> = (0..2000)
+            .map(|i| {
+                format!(
+                    r#"{{"id": {}, "category": "household", "price": {}, "currency": "EUR", "tags": ["{}", "sale"], "available": {}}}"#,
+                    i,
+                    rng.gen_range(1..1000),
+                    ["kitchen", "garden", "garage"][i % 3],
+                    i % 2 == 0
+                )
+            })
+            .collect();
+        let kvs: Vec<(u32, &[u8])> = (0..2000u32)
+            .map(|i| (i, values[i as usize].as_bytes()))
+            .collect();
+        let raw: usize = values.iter().map(|v| v.len()).sum();
+        for endian in [Endian::Little, Endian::Big] {
+            let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+                CHDGenerator::new(),
+                DictionaryValueWriter::new().dict_size(4096),
+            )
+            .verification(KeyVerification::Fingerprint(4))
+            .endian(endian);
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            serializer.write_to(&kvs, &mut cursor).unwrap();
+            let data = cursor.into_inner();
+
+            let mut map = PerfectHashMapDeserializer::<hasher::CityHash, u32, _, _>::new(
+                CHDReader::new(),
+                DictionaryValueReader::new(),
+            );
+            map.load_from_buffer(data.clone()).unwrap();
+            map.verify().unwrap();
+            let dict_len = map.value_deserializer.dictionary().len();
+            assert!(dict_len > 0 && dict_len <= 4096);
+            let info = map.info().unwrap();
+            assert!(((info.value_size - info.value_index_size) as usize) < raw / 2);
+            for (k, v) in &kvs {
+                assert_eq!(map.get(k).as_deref(), Some(*v));
+            }
+
+            // a size prefix beyond the largest value fails the load
+            let mut corrupted = data.clone();
+            let payload = (info.header_size + info.index_size + info.value_index_size) as usize;
+            corrupted[payload..payload + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(matches!(
+                map.load_from_buffer(corrupted),
+                Err(Error::Corrupted(_))
+            ));
+
+            let any = AnyPerfectHashMap::load_from_buffer(data).unwrap();
+            for (k, v) in &kvs {
+                assert_eq!(any.get_key(k).as_deref(), Some(*v));
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_size_test() {
+        let test_file = "./test_fixed.bin";
+        let values: Vec<[u8; 8]> = (0..1000u64).map(|i| (i * 7).to_ne_bytes()).collect();
+        let kvs: Vec<(u64, &[u8])> = (0..1000u64).map(|i| (i, &values[i as usize][..])).collect();
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::from_config(CHDGeneratorConfig::default().load_factor(0.8)),
+            FixedSizeValueWriter::new().align(8),
+        )
+        .verification(KeyVerification::Fingerprint(4));
+        serializer.write_to_file(&kvs, test_file).unwrap();
+
+        let mut map = PerfectHashMapDeserializer::<hasher::CityHash, u64, _, _>::new(
+            CHDReader::new(),
+            FixedSizeValueReader::new(),
+        );
+        map.load_from_mmap_file(test_file).unwrap();
+        map.verify().unwrap();
+        assert_eq!(map.value_deserializer.width(), 8);
+        for (k, v) in &kvs {
+            let value = map.get(k).unwrap();
+            assert_eq!(value, *v);
+            assert_eq!(value.as_ptr() as usize % 8, 0);
+        }
+        assert_eq!(map.get(&1000), None);
+
+        let any = AnyPerfectHashMap::load_from_mmap_file(test_file).unwrap();
+        std::fs::remove_file(test_file).unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(any.get_key(k).as_deref(), Some(*v));
+        }
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let kvs: Vec<(u64, &[u8])> = vec![(1, b"abcd"), (2, b"abc")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            FixedSizeValueWriter::new(),
+        );
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::WidthMismatch {
+                expected: 4,
+                found: 3
+            })
+        ));
+
+        let kvs: Vec<(u64, &[u8])> = vec![(1, b"abc"), (2, b"def")];
+        let mut serializer = PerfectHashMapSerializer::<hasher::CityHash, _, _, _>::new(
+            CHDGenerator::new(),
+            FixedSizeValueWriter::new().align(2),
+        );
+        assert!(matches!(
+            serializer.write_to(&kvs, &mut cursor),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[repr(C)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+    unsafe impl typed::Pod for Point {}
+    crate::impl_pod_value!(Point);
+
+    #[test]
+    fn typed_test() {
+        let test_file = "./test_typed.bin";
+        let names: Vec<String> = (0..100).map(|i| format!("name-{}", i)).collect();
+        let keys: Vec<u32> = (0..100).collect();
+        let kvs: Vec<(&u32, &str)> = keys
+            .iter()
+            .zip(&names)
+            .map(|(k, v)| (k, v.as_str()))
+            .collect();
+        PerfectHashMapWriter::<u32, str>::new()
+            .verification(KeyVerification::Fingerprint(4))
+            .write_to_file(&kvs, test_file)
+            .unwrap();
+        let map = PerfectHashMap::<u32, str>::load_from_mmap_file(test_file).unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(map.get(k), Some(*v));
+        }
+        assert_eq!(map.get(&1000), None);
+
+        let counts: Vec<u64> = keys.iter().map(|k| *k as u64 * 3).collect();
+        let kvs: Vec<(&str, &u64)> = names.iter().map(|v| v.as_str()).zip(&counts).collect();
+        PerfectHashMapWriter::<str, u64>::new()
+            .write_to_file(&kvs, test_file)
+            .unwrap();
+        let map = PerfectHashMap::<str, u64>::load_from_mmap_file(test_file).unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(map.get(k), Some(**v));
+        }
+
+        let points: Vec<Point> = keys
+            .iter()
+            .map(|k| Point {
+                x: *k as i32,
+                y: -(*k as i32),
+            })
+            .collect();
+        let kvs: Vec<(&u32, &Point)> = keys.iter().zip(&points).collect();
+        PerfectHashMapWriter::<u32, Point, hasher::Xxh3>::new()
+            .write_to_file(&kvs, test_file)
+            .unwrap();
+        let map =
+            PerfectHashMap::<u32, Point, hasher::Xxh3>::load_from_mmap_file(test_file).unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(map.get(k), Some(*v));
+        }
+
+        let lists: Vec<Vec<u64>> = keys.iter().map(|k| (0..*k as u64 % 7).collect()).collect();
+        let kvs: Vec<(&u32, &Vec<u64>)> = keys.iter().zip(&lists).collect();
+        PerfectHashMapWriter::<u32, Vec<u64>>::new()
+            .write_to_file(&kvs, test_file)
+            .unwrap();
+        let map = PerfectHashMap::<u32, Vec<u64>>::load_from_mmap_file(test_file).unwrap();
+        for (k, v) in &kvs {
+            assert_eq!(map.get(k), Some(v.as_slice()));
+        }
+        std::fs::remove_file(test_file).unwrap();
+
+        // place the file at an odd address
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        PerfectHashMapWriter::<u32, Vec<u64>>::new()
+            .write_to(&kvs, &mut cursor)
+            .unwrap();
+        let file = cursor.into_inner();
+        let mut buffer = Vec::with_capacity(file.len() + 8);
+        let shift = (9 - buffer.as_ptr() as usize % 8) % 8;
+        buffer.resize(shift, 0);
+        buffer.extend_from_slice(&file);
+        let data = Data::new(buffer).slice(shift..shift + file.len());
+        assert!(matches!(
+            PerfectHashMap::<u32, Vec<u64>>::load_from_data(data),
+            Err(Error::Unaligned)
+        ));
+
+        // pod values are raw bytes, which a foreign byte order would not convert
+        let foreign = match Endian::NATIVE {
+            Endian::Little => Endian::Big,
+            Endian::Big => Endian::Little,
+        };
+        assert!(matches!(
+            PerfectHashMapWriter::<u32, Vec<u64>>::new()
+                .endian(foreign)
+                .write_to(&kvs, std::io::Cursor::new(Vec::new())),
+            Err(Error::InvalidConfig(_))
+        ));
+        let kvs: Vec<(&u32, &u32)> = keys.iter().zip(&keys).collect();
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        PerfectHashMapWriter::<u32, u32>::new()
+            .endian(foreign)
+            .write_to(&kvs, &mut cursor)
+            .unwrap();
+        let map = PerfectHashMap::<u32, u32>::load_from_buffer(cursor.into_inner()).unwrap();
+        assert_eq!(map.get(&7), Some(7));
+    }
 }
@@ -0,0 +1,540 @@
+//! PTHash perfect hashing, after Pibiri and Trani, "PTHash: Revisiting FCH Minimal
+//! Perfect Hashing", 2021.
+//!
+//! Keys are distributed to buckets with a skew: 60% of the keys go to 30% of the
+//! buckets. Buckets are placed largest first, each with the first pilot which moves
+//! all its keys to free positions of the table. A lookup costs a hash, a pilot read
+//! and a multiplication, as fast as CHD in about a tenth of the space.
+//!
+//! In minimal mode the table has `key_count / load_factor` positions, and the keys
+//! placed past `key_count` are remapped to the free positions below it through an
+//! Elias–Fano coded list.
+
+use std::marker::PhantomData;
+
+use crate::compact::{self, Compact, Dictionary};
+use crate::elias_fano::{self, EliasFano};
+use crate::hasher::fmix64;
+use crate::{
+    any_as_u8_mut_slice, any_as_u8_slice, Data, Endian, Error, HashIndex, HashIndexSerializeInfo,
+    Hasher, IndexStats, PHashIndexDeserializer, PHashIndexSerializer, PHashKey, Result,
+};
+
+/// Pilots to try for a bucket before giving up on a seed.
+const MAX_PILOT: u64 = 1 << 24;
+/// Keys with the low 32 bits of their bucket hash below this go to the dense buckets.
+const DENSE_KEYS: u32 = (0.6 * (1u64 << 32) as f64) as u32;
+
+/// The table has more positions than keys and positions past the keys are remapped.
+const FLAG_MINIMAL: u32 = 1;
+
+/// How the pilot of every bucket is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PilotEncoding {
+    /// Every pilot in the bits of the largest one. Fastest to read.
+    #[default]
+    Compact,
+    /// The distinct pilots once, and per bucket an index into them. Smaller when
+    /// pilots repeat, which they mostly do.
+    Dictionary,
+    /// Prefix sums of the pilots, Elias–Fano coded. Smallest for low load factors.
+    EliasFano,
+}
+
+impl PilotEncoding {
+    fn id(&self) -> u32 {
+        match self {
+            PilotEncoding::Compact => 0,
+            PilotEncoding::Dictionary => 1,
+            PilotEncoding::EliasFano => 2,
+        }
+    }
+
+    fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(PilotEncoding::Compact),
+            1 => Some(PilotEncoding::Dictionary),
+            2 => Some(PilotEncoding::EliasFano),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PTHashGeneratorConfig {
+    /// Buckets are `c * key_count / log2(key_count)`. More buckets build faster but
+    /// take more space.
+    pub c: f32,
+    /// Keys per table position. Close to 1 saves space, 1 itself makes the last
+    /// buckets very slow to place.
+    pub load_factor: f32,
+    pub minimal: bool,
+    pub encoding: PilotEncoding,
+    /// First seed to try.
+    pub seed: u64,
+    /// How many seeds to try before giving up.
+    pub seed_retry: u32,
+}
+
+impl Default for PTHashGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            c: 5.0,
+            load_factor: 0.99,
+            minimal: false,
+            encoding: PilotEncoding::default(),
+            seed: 0,
+            seed_retry: 8,
+        }
+    }
+}
+
+impl PTHashGeneratorConfig {
+    pub fn c(mut self, c: f32) -> Self {
+        self.c = c;
+        self
+    }
+    pub fn load_factor(mut self, load_factor: f32) -> Self {
+        self.load_factor = load_factor;
+        self
+    }
+    pub fn minimal(mut self, minimal: bool) -> Self {
+        self.minimal = minimal;
+        self
+    }
+    pub fn encoding(mut self, encoding: PilotEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    pub fn seed_retry(mut self, seed_retry: u32) -> Self {
+        self.seed_retry = seed_retry;
+        self
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+#[repr(C, packed)]
+struct Header {
+    encoding: u32,
+    flag: u32,
+    key_count: u64,
+    table_size: u64,
+    bucket_count: u64,
+    seed: u64,
+}
+
+impl Header {
+    fn convert(mut self, endian: Endian) -> Self {
+        self.encoding = endian.convert_u32(self.encoding);
+        self.flag = endian.convert_u32(self.flag);
+        self.key_count = endian.convert_u64(self.key_count);
+        self.table_size = endian.convert_u64(self.table_size);
+        self.bucket_count = endian.convert_u64(self.bucket_count);
+        self.seed = endian.convert_u64(self.seed);
+        self
+    }
+}
+
+#[inline]
+fn remap(hash: u64, n: u64) -> u64 {
+    ((hash as u128 * n as u128) >> 64) as u64
+}
+
+fn key_hash<K: PHashKey + ?Sized, H: Hasher>(key: &K, seed: u64) -> (u64, u64) {
+    let mut hasher = H::with_seed(seed);
+    hasher.write(key.as_key_bytes().as_ref());
+    let hash = hasher.finish_u128();
+    ((hash >> 64) as u64, hash as u64)
+}
+
+fn next_seed(seed: u64) -> u64 {
+    seed.wrapping_add(0x9E3779B97F4A7C15)
+}
+
+/// The first `bucket_count * 3 / 10` buckets are dense.
+#[inline]
+fn bucket(hash: u64, bucket_count: u64) -> u64 {
+    let dense = bucket_count * 3 / 10;
+    if (hash as u32) < DENSE_KEYS {
+        remap(hash, dense)
+    } else {
+        dense + remap(hash, bucket_count - dense)
+    }
+}
+
+#[inline]
+fn position(hash: u64, pilot: u64, table_size: u64) -> u64 {
+    remap(
+        fmix64(hash ^ pilot.wrapping_mul(0x9E3779B97F4A7C15)),
+        table_size,
+    )
+}
+
+/// Pilots as stored by each [`PilotEncoding`].
+enum Pilots {
+    Compact(Compact),
+    Dictionary(Dictionary),
+    EliasFano(EliasFano),
+}
+
+impl Default for Pilots {
+    fn default() -> Self {
+        Pilots::Compact(Compact::default())
+    }
+}
+
+impl Pilots {
+    #[inline]
+    fn get(&self, bucket: usize) -> u64 {
+        match self {
+            Pilots::Compact(pilots) => pilots.get(bucket),
+            Pilots::Dictionary(pilots) => pilots.get(bucket),
+            Pilots::EliasFano(sums) => {
+                let (beg, end) = sums.get_pair(bucket);
+                end.wrapping_sub(beg)
+            }
+        }
+    }
+}
+
+pub struct PTHashGenerator<H> {
+    reader: Option<PTHashReader<H>>,
+    config: PTHashGeneratorConfig,
+}
+
+impl<H> Default for PTHashGenerator<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> PTHashGenerator<H> {
+    pub fn new() -> Self {
+        Self::from_config(PTHashGeneratorConfig::default())
+    }
+    pub fn from_config(config: PTHashGeneratorConfig) -> Self {
+        Self {
+            reader: None,
+            config,
+        }
+    }
+}
+
+impl<H> PTHashGenerator<H>
+where
+    H: Hasher,
+{
+    /// The section in `endian`, or `None` if some bucket could not be placed.
+    fn try_generate<K>(&self, keys: &[&K], seed: u64, endian: Endian) -> Result<Option<Vec<u8>>>
+    where
+        K: PHashKey + ?Sized,
+    {
+        let key_count = keys.len() as u64;
+        let table_size = ((key_count as f64 / self.config.load_factor as f64).ceil() as u64)
+            .max(key_count)
+            .max(1);
+        if table_size > u32::MAX as u64 {
+            return Err(Error::InvalidConfig(
+                "at most u32::MAX positions are supported",
+            ));
+        }
+        let log2 = (key_count as f64).log2().max(1.0);
+        let bucket_count = ((self.config.c as f64 * key_count as f64 / log2).ceil() as u64).max(1);
+
+        // position hashes ordered by bucket
+        let hashes: Vec<(u64, u64)> = keys.iter().map(|key| key_hash::<K, H>(key, seed)).collect();
+        let mut bucket_keys = vec![0usize; bucket_count as usize + 1];
+        for (hash, _) in &hashes {
+            bucket_keys[bucket(*hash, bucket_count) as usize + 1] += 1;
+        }
+        for bucket in 0..bucket_count as usize {
+            bucket_keys[bucket + 1] += bucket_keys[bucket];
+        }
+        let mut next = bucket_keys.clone();
+        let mut position_hashes = vec![0u64; keys.len()];
+        for (hash, position_hash) in hashes {
+            let bucket = bucket(hash, bucket_count) as usize;
+            position_hashes[next[bucket]] = position_hash;
+            next[bucket] += 1;
+        }
+        drop(next);
+
+        // largest buckets first
+        let mut order: Vec<usize> = (0..bucket_count as usize)
+            .filter(|bucket| bucket_keys[*bucket + 1] > bucket_keys[*bucket])
+            .collect();
+        order.sort_by_key(|bucket| {
+            std::cmp::Reverse(bucket_keys[*bucket + 1] - bucket_keys[*bucket])
+        });
+
+        let mut taken = vec![0u64; table_size.div_ceil(64) as usize];
+        let mut pilots = vec![0u64; bucket_count as usize];
+        let mut placed: Vec<u64> = Vec::new();
+        for bucket in order {
+            let bucket_hashes = &mut position_hashes[bucket_keys[bucket]..bucket_keys[bucket + 1]];
+            bucket_hashes.sort_unstable();
+            if bucket_hashes.windows(2).any(|pair| pair[0] == pair[1]) {
+                return Ok(None);
+            }
+            let pilot = (0..MAX_PILOT).find(|pilot| {
+                placed.clear();
+                for hash in bucket_hashes.iter() {
+                    let pos = position(*hash, *pilot, table_size);
+                    let (word, mask) = ((pos / 64) as usize, 1 << (pos % 64));
+                    if taken[word] & mask != 0 {
+                        for pos in &placed {
+                            taken[(*pos / 64) as usize] &= !(1 << (*pos % 64));
+                        }
+                        return false;
+                    }
+                    taken[word] |= mask;
+                    placed.push(pos);
+                }
+                true
+            });
+            match pilot {
+                Some(pilot) => pilots[bucket] = pilot,
+                None => return Ok(None),
+            }
+        }
+
+        let header = Header {
+            encoding: self.config.encoding.id(),
+            flag: if self.config.minimal { FLAG_MINIMAL } else { 0 },
+            key_count,
+            table_size,
+            bucket_count,
+            seed,
+        };
+        let mut section = Vec::new();
+        unsafe {
+            section.extend_from_slice(any_as_u8_slice(&header.convert(endian)));
+        }
+        match self.config.encoding {
+            PilotEncoding::Compact => compact::write(&pilots, &mut section, endian)?,
+            PilotEncoding::Dictionary => compact::write_dictionary(&pilots, &mut section, endian)?,
+            PilotEncoding::EliasFano => {
+                let mut sums = Vec::with_capacity(pilots.len() + 1);
+                sums.push(0);
+                for pilot in &pilots {
+                    sums.push(sums.last().unwrap() + pilot);
+                }
+                elias_fano::write(&sums, &mut section, endian)?;
+            }
+        }
+        if self.config.minimal {
+            // every position past the keys gets a free one below them, in order so
+            // the list does not decrease
+            let is_taken = |pos: u64| taken[(pos / 64) as usize] & (1 << (pos % 64)) != 0;
+            let mut free = (0..key_count).filter(|pos| !is_taken(*pos));
+            let mut last = 0;
+            let remapped: Vec<u64> = (key_count..table_size)
+                .map(|pos| {
+                    if is_taken(pos) {
+                        last = free.next().unwrap();
+                    }
+                    last
+                })
+                .collect();
+            elias_fano::write(&remapped, &mut section, endian)?;
+        }
+        Ok(Some(section))
+    }
+}
+
+impl<K, H> PHashIndexSerializer<K, H> for PTHashGenerator<H>
+where
+    H: Hasher,
+    K: PHashKey + ?Sized,
+{
+    const ID: u32 = 3;
+    type Deserializer = PTHashReader<H>;
+    fn generate<W>(
+        &mut self,
+        keys: &[&K],
+        writer: &mut W,
+        endian: Endian,
+    ) -> Result<HashIndexSerializeInfo>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        if !(1.0f32..=20.0f32).contains(&self.config.c) {
+            return Err(Error::InvalidConfig("c must be in 1.0..=20.0"));
+        }
+        if !(0.5f32..=1.0f32).contains(&self.config.load_factor) {
+            return Err(Error::InvalidConfig("load_factor must be in 0.5..=1.0"));
+        }
+        if self.config.seed_retry == 0 {
+            return Err(Error::InvalidConfig("seed_retry must be at least 1"));
+        }
+
+        let mut seed = self.config.seed;
+        let mut found = None;
+        for _ in 0..self.config.seed_retry {
+            if let Some(section) = self.try_generate(keys, seed, endian)? {
+                found = Some(section);
+                break;
+            }
+            seed = next_seed(seed);
+        }
+        let section = found.ok_or(Error::ConstructionFailed)?;
+        writer.write_all(&section)?;
+
+        let mut reader = PTHashReader::new();
+        <PTHashReader<H> as PHashIndexDeserializer<K, H>>::load(
+            &mut reader,
+            Data::new(section),
+            endian,
+        )?;
+        let max_hash_index =
+            <PTHashReader<H> as PHashIndexDeserializer<K, H>>::max_hash_index(&reader);
+        self.reader = Some(reader);
+
+        Ok(HashIndexSerializeInfo { max_hash_index })
+    }
+
+    fn pick(&self, key: &K) -> HashIndex {
+        self.reader.as_ref().unwrap().get_hash_index(key)
+    }
+}
+
+pub struct PTHashReader<H> {
+    header: Header,
+    pilots: Pilots,
+    pilot_bytes: usize,
+    remapped: EliasFano,
+    _pd0: PhantomData<H>,
+}
+
+impl<H> Default for PTHashReader<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> PTHashReader<H> {
+    pub fn new() -> Self {
+        Self {
+            header: Header::default(),
+            pilots: Pilots::default(),
+            pilot_bytes: 0,
+            remapped: EliasFano::default(),
+            _pd0: PhantomData,
+        }
+    }
+
+    pub fn encoding(&self) -> PilotEncoding {
+        PilotEncoding::from_id(self.header.encoding).unwrap_or_default()
+    }
+}
+
+impl<K, H> PHashIndexDeserializer<K, H> for PTHashReader<H>
+where
+    H: Hasher,
+    K: PHashKey + ?Sized,
+{
+    const ID: u32 = <PTHashGenerator<H> as PHashIndexSerializer<K, H>>::ID;
+    type Serializer = PTHashGenerator<H>;
+    fn load(&mut self, data: Data, endian: Endian) -> Result<()> {
+        let header_len = std::mem::size_of::<Header>();
+        if data.len() < header_len {
+            return Err(Error::Corrupted("index section too small"));
+        }
+        unsafe {
+            any_as_u8_mut_slice(&mut self.header).copy_from_slice(&data[..header_len]);
+        }
+        self.header = self.header.convert(endian);
+        let header = self.header;
+        if header.flag & !FLAG_MINIMAL != 0 {
+            return Err(Error::Corrupted("unknown pthash flag"));
+        }
+        let encoding = PilotEncoding::from_id(header.encoding)
+            .ok_or(Error::Corrupted("unknown pilot encoding"))?;
+        if header.table_size == 0
+            || header.table_size > u32::MAX as u64
+            || header.key_count > header.table_size
+            || header.bucket_count == 0
+        {
+            return Err(Error::Corrupted("invalid pthash header"));
+        }
+
+        let pilots_data = data.slice(header_len..data.len());
+        let bucket_count = header.bucket_count;
+        let (pilots, len) = match encoding {
+            PilotEncoding::Compact => {
+                let (pilots, len) = Compact::load(&pilots_data, endian)?;
+                if pilots.len() as u64 != bucket_count {
+                    return Err(Error::Corrupted("pthash bucket count mismatch"));
+                }
+                (Pilots::Compact(pilots), len)
+            }
+            PilotEncoding::Dictionary => {
+                let (pilots, len) = Dictionary::load(&pilots_data, endian)?;
+                if pilots.len() as u64 != bucket_count {
+                    return Err(Error::Corrupted("pthash bucket count mismatch"));
+                }
+                (Pilots::Dictionary(pilots), len)
+            }
+            PilotEncoding::EliasFano => {
+                let (sums, len) = EliasFano::load(&pilots_data, endian)?;
+                if sums.len() as u64 != bucket_count + 1 {
+                    return Err(Error::Corrupted("pthash bucket count mismatch"));
+                }
+                (Pilots::EliasFano(sums), len)
+            }
+        };
+        self.pilots = pilots;
+        self.pilot_bytes = len;
+
+        self.remapped = EliasFano::default();
+        if header.flag & FLAG_MINIMAL != 0 {
+            let remapped_data = pilots_data.slice(len..pilots_data.len());
+            let (remapped, _) = EliasFano::load(&remapped_data, endian)?;
+            if remapped.len() as u64 != header.table_size - header.key_count {
+                return Err(Error::Corrupted("pthash free position count mismatch"));
+            }
+            self.remapped = remapped;
+        }
+        Ok(())
+    }
+    fn max_hash_index(&self) -> HashIndex {
+        if self.header.flag & FLAG_MINIMAL != 0 {
+            self.header.key_count as HashIndex
+        } else {
+            self.header.table_size as HashIndex
+        }
+    }
+    fn stats(&self) -> IndexStats {
+        let mut histogram = std::collections::BTreeMap::<u64, u64>::new();
+        for bucket in 0..self.header.bucket_count as usize {
+            let bits = 64 - self.pilots.get(bucket).leading_zeros() as u64;
+            *histogram.entry(bits).or_default() += 1;
+        }
+        IndexStats {
+            max_hash_index: <Self as PHashIndexDeserializer<K, H>>::max_hash_index(self),
+            fields: vec![
+                ("key_count", self.header.key_count),
+                ("table_size", self.header.table_size),
+                ("bucket_count", self.header.bucket_count),
+                ("seed", self.header.seed),
+                ("encoding", self.header.encoding as u64),
+                ("pilot_bytes", self.pilot_bytes as u64),
+            ],
+            histogram: histogram.into_iter().collect(),
+        }
+    }
+    fn get_hash_index(&self, key: &K) -> HashIndex {
+        let (hash, position_hash) = key_hash::<K, H>(key, self.header.seed);
+        let bucket = bucket(hash, self.header.bucket_count);
+        let pilot = self.pilots.get(bucket as usize);
+        let pos = position(position_hash, pilot, self.header.table_size);
+        if pos >= self.header.key_count && self.header.flag & FLAG_MINIMAL != 0 {
+            return self.remapped.get((pos - self.header.key_count) as usize) as HashIndex;
+        }
+        pos as HashIndex
+    }
+}
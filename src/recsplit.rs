@@ -0,0 +1,668 @@
+//! RecSplit minimal perfect hashing, after Esposito, Mueller Graf and Vigna, "RecSplit:
+//! Minimal Perfect Hashing via Recursive Splitting", 2020.
+//!
+//! Keys are distributed to buckets of about `bucket_size` keys. Each bucket is split
+//! recursively, first in halves at multiples of an upper aggregation unit, then into
+//! parts of a lower aggregation unit, then into leaves of `leaf_size` keys, and every
+//! leaf is mapped bijectively onto its positions. Every split and leaf stores the
+//! number of the first hash seed which works, Golomb-Rice coded. That takes about 1.8
+//! bits per key with leaves of 8 keys.
+
+use std::marker::PhantomData;
+
+use crate::elias_fano::{self, EliasFano};
+use crate::hasher::fmix64;
+use crate::{
+    any_as_u8_mut_slice, any_as_u8_slice, Data, Endian, Error, HashIndex, HashIndexSerializeInfo,
+    Hasher, IndexStats, PHashIndexDeserializer, PHashIndexSerializer, PHashKey, Result,
+};
+
+#[derive(Debug, Clone)]
+pub struct RecSplitGeneratorConfig {
+    /// Keys per leaf, 1 to 16. Larger leaves take less space but are exponentially
+    /// slower to build.
+    pub leaf_size: u32,
+    /// Average keys per bucket. Larger buckets take less space but make lookups
+    /// walk deeper trees.
+    pub bucket_size: u32,
+    /// First seed to try.
+    pub seed: u64,
+    /// How many seeds to try, which only matters if keys collide in 128 bits.
+    pub seed_retry: u32,
+}
+
+impl Default for RecSplitGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            leaf_size: 8,
+            bucket_size: 100,
+            seed: 0,
+            seed_retry: 8,
+        }
+    }
+}
+
+impl RecSplitGeneratorConfig {
+    pub fn leaf_size(mut self, leaf_size: u32) -> Self {
+        self.leaf_size = leaf_size;
+        self
+    }
+    pub fn bucket_size(mut self, bucket_size: u32) -> Self {
+        self.bucket_size = bucket_size;
+        self
+    }
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    pub fn seed_retry(mut self, seed_retry: u32) -> Self {
+        self.seed_retry = seed_retry;
+        self
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+#[repr(C, packed)]
+struct Header {
+    leaf_size: u32,
+    max_bucket_size: u32,
+    key_count: u64,
+    bucket_count: u64,
+    seed: u64,
+    fixed_words: u64,
+    unary_words: u64,
+}
+
+impl Header {
+    fn convert(mut self, endian: Endian) -> Self {
+        self.leaf_size = endian.convert_u32(self.leaf_size);
+        self.max_bucket_size = endian.convert_u32(self.max_bucket_size);
+        self.key_count = endian.convert_u64(self.key_count);
+        self.bucket_count = endian.convert_u64(self.bucket_count);
+        self.seed = endian.convert_u64(self.seed);
+        self.fixed_words = endian.convert_u64(self.fixed_words);
+        self.unary_words = endian.convert_u64(self.unary_words);
+        self
+    }
+}
+
+/// The shape of the split trees, derived from the leaf size as in the paper.
+#[derive(Default, Clone, Copy)]
+struct Shape {
+    leaf: usize,
+    lower: usize,
+    upper: usize,
+}
+
+impl Shape {
+    fn new(leaf_size: usize) -> Self {
+        let lower_leaves = 2.max((0.35 * leaf_size as f64 + 0.5).ceil() as usize);
+        let upper_lowers = 2.max((0.21 * leaf_size as f64 + 0.9).ceil() as usize);
+        let lower = leaf_size * lower_leaves;
+        Self {
+            leaf: leaf_size,
+            lower,
+            upper: lower * upper_lowers,
+        }
+    }
+
+    /// Size of the parts a node of `m > leaf` keys is split into, all but the last.
+    #[inline]
+    fn unit(&self, m: usize) -> usize {
+        if m <= self.lower {
+            self.leaf
+        } else if m <= self.upper {
+            self.lower
+        } else {
+            (m / 2).div_ceil(self.upper) * self.upper
+        }
+    }
+
+    /// Sizes of the children of a node of `m > leaf` keys.
+    fn parts(&self, m: usize) -> impl Iterator<Item = usize> {
+        let unit = self.unit(m);
+        (0..m.div_ceil(unit)).map(move |part| unit.min(m - part * unit))
+    }
+}
+
+/// Largest Golomb-Rice parameter, so that shifts by it never overflow.
+const MAX_RICE: u8 = 48;
+
+/// Golomb-Rice parameter for the seed numbers of nodes of `m` keys, from the
+/// probability that a seed splits a node as required.
+fn rice_parameter(shape: &Shape, m: usize) -> u8 {
+    if m <= 1 {
+        return 0;
+    }
+    let ln_factorial = |n: usize| (2..=n).map(|i| (i as f64).ln()).sum::<f64>();
+    let ln_p = if m <= shape.leaf {
+        ln_factorial(m) - m as f64 * (m as f64).ln()
+    } else {
+        shape.parts(m).fold(ln_factorial(m), |ln_p, part| {
+            ln_p - ln_factorial(part) + part as f64 * (part as f64 / m as f64).ln()
+        })
+    };
+    // seed numbers are geometric with success probability p, for which a Golomb
+    // modulus of ln(2) / p is close to optimal
+    let modulus = -std::f64::consts::LN_2 / (-ln_p.exp()).ln_1p();
+    modulus.log2().floor().clamp(0.0, MAX_RICE as f64) as u8
+}
+
+/// Fixed bits and number of codes of every subtree size, to skip subtrees.
+fn skip_tables(shape: &Shape, rice: &[u8]) -> (Vec<u64>, Vec<u64>) {
+    let mut fixed = vec![0u64; rice.len()];
+    let mut nodes = vec![0u64; rice.len()];
+    for m in 2..rice.len() {
+        fixed[m] = rice[m] as u64;
+        nodes[m] = 1;
+        if m > shape.leaf {
+            for part in shape.parts(m) {
+                fixed[m] += fixed[part];
+                nodes[m] += nodes[part];
+            }
+        }
+    }
+    (fixed, nodes)
+}
+
+#[inline]
+fn remap(hash: u64, n: usize) -> usize {
+    ((hash as u128 * n as u128) >> 64) as usize
+}
+
+/// The hash of a key at tree depth `level` under seed number `x`.
+#[inline]
+fn node_hash(fingerprint: u64, x: u64, level: u32) -> u64 {
+    fmix64(fingerprint ^ fmix64(x ^ ((level as u64) << 48)))
+}
+
+fn key_hash<K: PHashKey + ?Sized, H: Hasher>(key: &K, seed: u64) -> (u64, u64) {
+    let mut hasher = H::with_seed(seed);
+    hasher.write(key.as_key_bytes().as_ref());
+    let hash = hasher.finish_u128();
+    ((hash >> 64) as u64, hash as u64)
+}
+
+fn next_seed(seed: u64) -> u64 {
+    seed.wrapping_add(0x9E3779B97F4A7C15)
+}
+
+#[derive(Default)]
+struct BitWriter {
+    words: Vec<u64>,
+    len: u64,
+}
+
+impl BitWriter {
+    fn push_bits(&mut self, bits: u64, n: u8) {
+        if n == 0 {
+            return;
+        }
+        let shift = self.len % 64;
+        if shift == 0 {
+            self.words.push(0);
+        }
+        *self.words.last_mut().unwrap() |= bits << shift;
+        if shift + n as u64 > 64 {
+            self.words.push(bits >> (64 - shift));
+        }
+        self.len += n as u64;
+    }
+
+    /// `q` zeros and a one.
+    fn push_unary(&mut self, q: u64) {
+        self.len += q;
+        self.words.resize(self.len.div_ceil(64) as usize, 0);
+        if self.len.is_multiple_of(64) {
+            self.words.push(0);
+        }
+        *self.words.last_mut().unwrap() |= 1 << (self.len % 64);
+        self.len += 1;
+    }
+}
+
+struct Builder<'a> {
+    shape: Shape,
+    rice: &'a [u8],
+    fixed: BitWriter,
+    unary: BitWriter,
+    counts: Vec<usize>,
+}
+
+impl Builder<'_> {
+    /// Encode the tree of a node whose keys have distinct `fingerprints`, reordering
+    /// them by child.
+    fn build(&mut self, fingerprints: &mut [u64], level: u32) {
+        let m = fingerprints.len();
+        if m <= 1 {
+            return;
+        }
+        let x = if m <= self.shape.leaf {
+            (0u64..)
+                .find(|x| {
+                    let mut used = 0u64;
+                    fingerprints.iter().all(|fingerprint| {
+                        let bit = 1 << remap(node_hash(*fingerprint, *x, level), m);
+                        let free = used & bit == 0;
+                        used |= bit;
+                        free
+                    })
+                })
+                .unwrap()
+        } else {
+            let unit = self.shape.unit(m);
+            let parts = m.div_ceil(unit);
+            (0u64..)
+                .find(|x| {
+                    self.counts.clear();
+                    self.counts.resize(parts, 0);
+                    for fingerprint in fingerprints.iter() {
+                        self.counts[remap(node_hash(*fingerprint, *x, level), m) / unit] += 1;
+                    }
+                    self.counts[..parts - 1].iter().all(|count| *count == unit)
+                })
+                .unwrap()
+        };
+
+        let k = self.rice[m];
+        self.fixed.push_bits(x & ((1 << k) - 1), k);
+        self.unary.push_unary(x >> k);
+
+        if m > self.shape.leaf {
+            let unit = self.shape.unit(m);
+            fingerprints
+                .sort_by_key(|fingerprint| remap(node_hash(*fingerprint, x, level), m) / unit);
+            for part in fingerprints.chunks_mut(unit) {
+                self.build(part, level + 1);
+            }
+        }
+    }
+}
+
+pub struct RecSplitGenerator<H> {
+    reader: Option<RecSplitReader<H>>,
+    config: RecSplitGeneratorConfig,
+}
+
+impl<H> Default for RecSplitGenerator<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> RecSplitGenerator<H> {
+    pub fn new() -> Self {
+        Self::from_config(RecSplitGeneratorConfig::default())
+    }
+    pub fn from_config(config: RecSplitGeneratorConfig) -> Self {
+        Self {
+            reader: None,
+            config,
+        }
+    }
+}
+
+impl<H> RecSplitGenerator<H>
+where
+    H: Hasher,
+{
+    /// The section in `endian`, or `None` if two keys have the same fingerprint.
+    fn try_generate<K>(&self, keys: &[&K], seed: u64, endian: Endian) -> Result<Option<Vec<u8>>>
+    where
+        K: PHashKey + ?Sized,
+    {
+        let bucket_count = keys.len().div_ceil(self.config.bucket_size as usize).max(1);
+
+        // fingerprints ordered by bucket
+        let hashes: Vec<(u64, u64)> = keys.iter().map(|key| key_hash::<K, H>(key, seed)).collect();
+        let mut bucket_keys = vec![0u64; bucket_count + 1];
+        for (hash, _) in &hashes {
+            bucket_keys[remap(*hash, bucket_count) + 1] += 1;
+        }
+        for bucket in 0..bucket_count {
+            bucket_keys[bucket + 1] += bucket_keys[bucket];
+        }
+        let mut next = bucket_keys.clone();
+        let mut fingerprints = vec![0u64; keys.len()];
+        for (hash, fingerprint) in hashes {
+            let bucket = remap(hash, bucket_count);
+            fingerprints[next[bucket] as usize] = fingerprint;
+            next[bucket] += 1;
+        }
+        drop(next);
+
+        let max_bucket_size = bucket_keys
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .max()
+            .unwrap_or(0) as usize;
+        if max_bucket_size > u32::MAX as usize {
+            return Err(Error::ConstructionFailed);
+        }
+        let shape = Shape::new(self.config.leaf_size as usize);
+        let rice: Vec<u8> = (0..=max_bucket_size)
+            .map(|m| rice_parameter(&shape, m))
+            .collect();
+
+        let mut builder = Builder {
+            shape,
+            rice: &rice,
+            fixed: BitWriter::default(),
+            unary: BitWriter::default(),
+            counts: Vec::new(),
+        };
+        let mut fixed_pos = Vec::with_capacity(bucket_count + 1);
+        let mut unary_pos = Vec::with_capacity(bucket_count + 1);
+        for bucket in 0..bucket_count {
+            fixed_pos.push(builder.fixed.len);
+            unary_pos.push(builder.unary.len);
+            let bucket =
+                &mut fingerprints[bucket_keys[bucket] as usize..bucket_keys[bucket + 1] as usize];
+            bucket.sort_unstable();
+            if bucket.windows(2).any(|pair| pair[0] == pair[1]) {
+                return Ok(None);
+            }
+            builder.build(bucket, 0);
+        }
+        fixed_pos.push(builder.fixed.len);
+        unary_pos.push(builder.unary.len);
+
+        let header = Header {
+            leaf_size: self.config.leaf_size,
+            max_bucket_size: max_bucket_size as u32,
+            key_count: keys.len() as u64,
+            bucket_count: bucket_count as u64,
+            seed,
+            fixed_words: builder.fixed.words.len() as u64,
+            unary_words: builder.unary.words.len() as u64,
+        };
+        let mut section = Vec::new();
+        unsafe {
+            section.extend_from_slice(any_as_u8_slice(&header.convert(endian)));
+        }
+        section.extend_from_slice(&rice);
+        section.resize(section.len().next_multiple_of(8), 0);
+        for word in builder.fixed.words.iter().chain(&builder.unary.words) {
+            section.extend_from_slice(&endian.convert_u64(*word).to_ne_bytes());
+        }
+        elias_fano::write(&bucket_keys, &mut section, endian)?;
+        elias_fano::write(&fixed_pos, &mut section, endian)?;
+        elias_fano::write(&unary_pos, &mut section, endian)?;
+        Ok(Some(section))
+    }
+}
+
+impl<K, H> PHashIndexSerializer<K, H> for RecSplitGenerator<H>
+where
+    H: Hasher,
+    K: PHashKey + ?Sized,
+{
+    const ID: u32 = 1;
+    type Deserializer = RecSplitReader<H>;
+    fn generate<W>(
+        &mut self,
+        keys: &[&K],
+        writer: &mut W,
+        endian: Endian,
+    ) -> Result<HashIndexSerializeInfo>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        if !(1..=16).contains(&self.config.leaf_size) {
+            return Err(Error::InvalidConfig("leaf_size must be in 1..=16"));
+        }
+        if !(1..=2000).contains(&self.config.bucket_size) {
+            return Err(Error::InvalidConfig("bucket_size must be in 1..=2000"));
+        }
+        if self.config.seed_retry == 0 {
+            return Err(Error::InvalidConfig("seed_retry must be at least 1"));
+        }
+        if keys.len() > u32::MAX as usize {
+            return Err(Error::InvalidConfig("at most u32::MAX keys are supported"));
+        }
+
+        let mut seed = self.config.seed;
+        let mut found = None;
+        for _ in 0..self.config.seed_retry {
+            if let Some(section) = self.try_generate(keys, seed, endian)? {
+                found = Some(section);
+                break;
+            }
+            seed = next_seed(seed);
+        }
+        let section = found.ok_or(Error::ConstructionFailed)?;
+        writer.write_all(&section)?;
+
+        let mut reader = RecSplitReader::new();
+        <RecSplitReader<H> as PHashIndexDeserializer<K, H>>::load(
+            &mut reader,
+            Data::new(section),
+            endian,
+        )?;
+        self.reader = Some(reader);
+
+        Ok(HashIndexSerializeInfo {
+            max_hash_index: keys.len() as u32,
+        })
+    }
+
+    fn pick(&self, key: &K) -> HashIndex {
+        self.reader.as_ref().unwrap().get_hash_index(key)
+    }
+}
+
+pub struct RecSplitReader<H> {
+    header: Header,
+    shape: Shape,
+    rice: Vec<u8>,
+    skip_fixed: Vec<u64>,
+    skip_nodes: Vec<u64>,
+    fixed: Data,
+    unary: Data,
+    bucket_keys: EliasFano,
+    fixed_pos: EliasFano,
+    unary_pos: EliasFano,
+    endian: Endian,
+    _pd0: PhantomData<H>,
+}
+
+impl<H> Default for RecSplitReader<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> RecSplitReader<H> {
+    pub fn new() -> Self {
+        Self {
+            header: Header::default(),
+            shape: Shape::default(),
+            rice: Vec::new(),
+            skip_fixed: Vec::new(),
+            skip_nodes: Vec::new(),
+            fixed: Data::default(),
+            unary: Data::default(),
+            bucket_keys: EliasFano::default(),
+            fixed_pos: EliasFano::default(),
+            unary_pos: EliasFano::default(),
+            endian: Endian::NATIVE,
+            _pd0: PhantomData,
+        }
+    }
+
+    /// Word `index` of `words`, 0 past the end so corrupted files do not panic.
+    #[inline]
+    fn word(&self, words: &Data, index: usize) -> u64 {
+        match words.get(index * 8..index * 8 + 8) {
+            Some(bytes) => self
+                .endian
+                .convert_u64(u64::from_ne_bytes(bytes.try_into().unwrap())),
+            None => 0,
+        }
+    }
+
+    #[inline]
+    fn read_fixed(&self, pos: &mut u64, k: u8) -> u64 {
+        if k == 0 {
+            return 0;
+        }
+        let (word, shift) = ((*pos / 64) as usize, *pos % 64);
+        let mut bits = self.word(&self.fixed, word) >> shift;
+        if shift + k as u64 > 64 {
+            bits |= self.word(&self.fixed, word + 1) << (64 - shift);
+        }
+        *pos += k as u64;
+        bits & ((1 << k) - 1)
+    }
+
+    /// Skip `n` unary codes, returning the zeros of the last one.
+    #[inline]
+    fn skip_unary(&self, pos: &mut u64, mut n: u64) -> u64 {
+        let start = *pos;
+        let mut last_start = start;
+        let mut word_index = (*pos / 64) as usize;
+        let mut word = self.word(&self.unary, word_index) & (u64::MAX << (*pos % 64));
+        while n > 0 {
+            if word == 0 {
+                word_index += 1;
+                if word_index >= self.header.unary_words as usize {
+                    return 0;
+                }
+                word = self.word(&self.unary, word_index);
+                continue;
+            }
+            let one = word_index as u64 * 64 + word.trailing_zeros() as u64;
+            word &= word - 1;
+            n -= 1;
+            if n == 0 {
+                *pos = one + 1;
+                return one - last_start;
+            }
+            last_start = one + 1;
+        }
+        0
+    }
+}
+
+impl<K, H> PHashIndexDeserializer<K, H> for RecSplitReader<H>
+where
+    H: Hasher,
+    K: PHashKey + ?Sized,
+{
+    const ID: u32 = <RecSplitGenerator<H> as PHashIndexSerializer<K, H>>::ID;
+    type Serializer = RecSplitGenerator<H>;
+    fn load(&mut self, data: Data, endian: Endian) -> Result<()> {
+        let header_len = std::mem::size_of::<Header>();
+        if data.len() < header_len {
+            return Err(Error::Corrupted("index section too small"));
+        }
+        unsafe {
+            any_as_u8_mut_slice(&mut self.header).copy_from_slice(&data[..header_len]);
+        }
+        self.header = self.header.convert(endian);
+        self.endian = endian;
+        let header = self.header;
+        let leaf_size = header.leaf_size;
+        if !(1..=16).contains(&leaf_size) || header.bucket_count == 0 {
+            return Err(Error::Corrupted("invalid recsplit header"));
+        }
+
+        let rice_end = header_len + header.max_bucket_size as usize + 1;
+        let fixed_beg = rice_end.next_multiple_of(8);
+        let unary_beg = (header.fixed_words as usize)
+            .checked_mul(8)
+            .and_then(|len| len.checked_add(fixed_beg));
+        let unary_end = unary_beg
+            .zip((header.unary_words as usize).checked_mul(8))
+            .and_then(|(beg, len)| beg.checked_add(len))
+            .filter(|end| *end <= data.len())
+            .ok_or(Error::Corrupted("recsplit codes out of bounds"))?;
+        let unary_beg = unary_beg.unwrap();
+        if data[header_len..rice_end].iter().any(|k| *k > MAX_RICE) {
+            return Err(Error::Corrupted("invalid recsplit rice parameter"));
+        }
+        self.shape = Shape::new(header.leaf_size as usize);
+        self.rice = data[header_len..rice_end].to_vec();
+        (self.skip_fixed, self.skip_nodes) = skip_tables(&self.shape, &self.rice);
+        self.fixed = data.slice(fixed_beg..unary_beg);
+        self.unary = data.slice(unary_beg..unary_end);
+
+        let mut pos = unary_end;
+        let mut next_ef = || -> Result<EliasFano> {
+            let (ef, len) = EliasFano::load(&data.slice(pos..data.len()), endian)?;
+            pos += len;
+            if ef.len() as u64 != header.bucket_count + 1 {
+                return Err(Error::Corrupted("recsplit bucket count mismatch"));
+            }
+            Ok(ef)
+        };
+        self.bucket_keys = next_ef()?;
+        self.fixed_pos = next_ef()?;
+        self.unary_pos = next_ef()?;
+        let last = header.bucket_count as usize - 1;
+        if self.bucket_keys.get_pair(last).1 != header.key_count {
+            return Err(Error::Corrupted("recsplit key count mismatch"));
+        }
+        Ok(())
+    }
+    fn max_hash_index(&self) -> HashIndex {
+        self.header.key_count as HashIndex
+    }
+    fn stats(&self) -> IndexStats {
+        let mut histogram = std::collections::BTreeMap::<u64, u64>::new();
+        for bucket in 0..self.header.bucket_count as usize {
+            let (beg, end) = self.bucket_keys.get_pair(bucket);
+            *histogram.entry(end - beg).or_default() += 1;
+        }
+        let code_bits = self
+            .fixed_pos
+            .get_pair(self.header.bucket_count as usize - 1)
+            .1
+            + self
+                .unary_pos
+                .get_pair(self.header.bucket_count as usize - 1)
+                .1;
+        IndexStats {
+            max_hash_index: self.header.key_count as HashIndex,
+            fields: vec![
+                ("key_count", self.header.key_count),
+                ("bucket_count", self.header.bucket_count),
+                ("leaf_size", self.header.leaf_size as u64),
+                ("seed", self.header.seed),
+                ("code_bits", code_bits),
+            ],
+            histogram: histogram.into_iter().collect(),
+        }
+    }
+    fn get_hash_index(&self, key: &K) -> HashIndex {
+        let (hash, fingerprint) = key_hash::<K, H>(key, self.header.seed);
+        let bucket = remap(hash, self.header.bucket_count as usize);
+        let (mut offset, end) = self.bucket_keys.get_pair(bucket);
+        let mut m = end.wrapping_sub(offset) as usize;
+        if m == 0 || m >= self.rice.len() {
+            // only absent keys, or corrupted files
+            return 0;
+        }
+        let mut fixed = self.fixed_pos.get_pair(bucket).0;
+        let mut unary = self.unary_pos.get_pair(bucket).0;
+        let mut level = 0;
+        while m > 1 {
+            let k = self.rice[m];
+            let x = (self.skip_unary(&mut unary, 1) << k) | self.read_fixed(&mut fixed, k);
+            let r = remap(node_hash(fingerprint, x, level), m);
+            if m <= self.shape.leaf {
+                offset += r as u64;
+                break;
+            }
+            let unit = self.shape.unit(m);
+            let part = r / unit;
+            // skip the trees of the preceding parts, which are all full
+            fixed += part as u64 * self.skip_fixed[unit];
+            self.skip_unary(&mut unary, part as u64 * self.skip_nodes[unit]);
+            offset += (part * unit) as u64;
+            m = unit.min(m - part * unit);
+            level += 1;
+        }
+        offset as HashIndex
+    }
+}
@@ -0,0 +1,347 @@
+use std::marker::PhantomData;
+
+use crate::chd::{CHDGenerator, CHDGeneratorConfig, CHDReader};
+use crate::hasher::CityHash;
+use crate::value::{DefaultHashValueReader, DefaultHashValueWriter};
+use crate::{
+    Data, Endian, Error, Hasher, KeyVerification, PHashKey, PerfectHashMapDeserializer,
+    PerfectHashMapSerializer, Result,
+};
+
+/// A value type of [`PerfectHashMap`] and how it is stored.
+pub trait PHashValue {
+    /// What lookups return, borrowed from the map for zero-copy encodings.
+    type Ref<'a>
+    where
+        Self: 'a;
+
+    /// Alignment `decode` needs, at most 8.
+    const ALIGN: usize = 1;
+
+    /// The encoding is the in-memory bytes, which only a machine of the writer's byte
+    /// order reads back, so maps of it are written and loaded in native endian only.
+    const NATIVE_ENDIAN: bool = false;
+
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// `None` if `bytes` is not a valid encoding, which only happens for corrupted
+    /// files.
+    fn decode(bytes: &[u8]) -> Option<Self::Ref<'_>>;
+}
+
+impl PHashValue for str {
+    type Ref<'a> = &'a str;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<&str> {
+        std::str::from_utf8(bytes).ok()
+    }
+}
+
+impl PHashValue for String {
+    type Ref<'a> = &'a str;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<&str> {
+        std::str::from_utf8(bytes).ok()
+    }
+}
+
+// Integers and floats are copied out in little endian, so the files are portable.
+macro_rules! impl_num_value {
+    ($($ty:ty),*) => {
+        $(
+            impl PHashValue for $ty {
+                type Ref<'a> = $ty;
+
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> Option<$ty> {
+                    Some(<$ty>::from_le_bytes(bytes.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_num_value!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Plain old data which is stored as its in-memory bytes and read in place.
+///
+/// Such values are only readable on a machine with the same byte order as the writer,
+/// so their maps are always in native endian.
+///
+/// # Safety
+///
+/// The type must have no padding bytes, no pointers, and every bit pattern must be a
+/// valid value, e.g. a `#[repr(C)]` struct of integers without gaps.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+pub fn pod_encode<T: Pod>(values: &[T], out: &mut Vec<u8>) {
+    out.extend_from_slice(unsafe { crate::any_array_as_u8_slice(values) });
+}
+
+/// The values `bytes` holds, or `None` if it is misaligned or not a whole number of
+/// values.
+pub fn pod_decode<T: Pod>(bytes: &[u8]) -> Option<&[T]> {
+    let size = std::mem::size_of::<T>();
+    if size == 0
+        || !bytes.len().is_multiple_of(size)
+        || !(bytes.as_ptr() as usize).is_multiple_of(std::mem::align_of::<T>())
+    {
+        return None;
+    }
+    Some(unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const T, bytes.len() / size) })
+}
+
+/// Implement [`PHashValue`] for [`Pod`] types, which are then read in place.
+///
+/// ```ignore
+/// #[derive(Clone, Copy)]
+/// #[repr(C)]
+/// struct Point {
+///     x: i32,
+///     y: i32,
+/// }
+/// unsafe impl phash::typed::Pod for Point {}
+/// phash::impl_pod_value!(Point);
+/// ```
+#[macro_export]
+macro_rules! impl_pod_value {
+    ($($ty:ty),*) => {
+        $(
+            impl $crate::typed::PHashValue for $ty {
+                type Ref<'a> = &'a $ty;
+
+                const ALIGN: usize = std::mem::align_of::<$ty>();
+
+                const NATIVE_ENDIAN: bool = true;
+
+                fn encode(&self, out: &mut Vec<u8>) {
+                    $crate::typed::pod_encode(std::slice::from_ref(self), out);
+                }
+
+                fn decode(bytes: &[u8]) -> Option<&$ty> {
+                    match $crate::typed::pod_decode::<$ty>(bytes)? {
+                        [value] => Some(value),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl<T: Pod> PHashValue for [T] {
+    type Ref<'a> = &'a [T];
+
+    const ALIGN: usize = std::mem::align_of::<T>();
+
+    const NATIVE_ENDIAN: bool = true;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        pod_encode(self, out);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<&[T]> {
+        pod_decode(bytes)
+    }
+}
+
+impl<T: Pod> PHashValue for Vec<T> {
+    type Ref<'a> = &'a [T];
+
+    const ALIGN: usize = std::mem::align_of::<T>();
+
+    const NATIVE_ENDIAN: bool = true;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        pod_encode(self, out);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<&[T]> {
+        pod_decode(bytes)
+    }
+}
+
+/// Writes maps of typed values, read back with [`PerfectHashMap`].
+pub struct PerfectHashMapWriter<K: ?Sized, V: ?Sized, H = CityHash> {
+    config: CHDGeneratorConfig,
+    verification: KeyVerification,
+    endian: Endian,
+    _pd0: PhantomData<H>,
+    _pd1: PhantomData<K>,
+    _pd2: PhantomData<V>,
+}
+
+impl<K, V, H> Default for PerfectHashMapWriter<K, V, H>
+where
+    K: PHashKey + ?Sized,
+    V: PHashValue + ?Sized,
+    H: Hasher,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, H> PerfectHashMapWriter<K, V, H>
+where
+    K: PHashKey + ?Sized,
+    V: PHashValue + ?Sized,
+    H: Hasher,
+{
+    pub fn new() -> Self {
+        Self::from_config(CHDGeneratorConfig::default())
+    }
+
+    pub fn from_config(config: CHDGeneratorConfig) -> Self {
+        Self {
+            config,
+            verification: KeyVerification::default(),
+            endian: Endian::NATIVE,
+            _pd0: PhantomData,
+            _pd1: PhantomData,
+            _pd2: PhantomData,
+        }
+    }
+
+    pub fn verification(mut self, verification: KeyVerification) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    pub fn write_to_file<P>(&self, kvs: &[(&K, &V)], path: P) -> Result<()>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let file = std::fs::File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path.as_ref())?;
+        self.write_to(kvs, file)
+    }
+
+    pub fn write_to<W>(&self, kvs: &[(&K, &V)], output: W) -> Result<()>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        if V::NATIVE_ENDIAN && self.endian != Endian::NATIVE {
+            return Err(Error::InvalidConfig(
+                "pod values are only written in native endian",
+            ));
+        }
+        let mut bytes = Vec::new();
+        let mut ends = Vec::with_capacity(kvs.len());
+        for (_, value) in kvs {
+            value.encode(&mut bytes);
+            ends.push(bytes.len());
+        }
+        let mut beg = 0;
+        let kvs: Vec<(&K, &[u8])> = kvs
+            .iter()
+            .zip(ends)
+            .map(|((key, _), end)| {
+                let value = &bytes[beg..end];
+                beg = end;
+                (*key, value)
+            })
+            .collect();
+
+        let mut serializer = PerfectHashMapSerializer::<H, _, _, _>::new(
+            CHDGenerator::from_config(self.config.clone()),
+            DefaultHashValueWriter::new().align(V::ALIGN),
+        )
+        .verification(self.verification)
+        .endian(self.endian);
+        serializer.write_to(&kvs, output)
+    }
+}
+
+/// A loaded map which decodes its values as `V`.
+pub struct PerfectHashMap<K, V, H = CityHash>
+where
+    K: PHashKey + ?Sized,
+    V: PHashValue + ?Sized,
+    H: Hasher,
+{
+    inner: PerfectHashMapDeserializer<H, K, CHDReader<H>, DefaultHashValueReader>,
+    _pd: PhantomData<V>,
+}
+
+impl<K, V, H> PerfectHashMap<K, V, H>
+where
+    K: PHashKey + ?Sized,
+    V: PHashValue + ?Sized,
+    H: Hasher,
+{
+    pub fn load_from_mmap_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let file = std::fs::File::options().read(true).open(path)?;
+        let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+        Self::load_from_data(Data::new(mmap))
+    }
+
+    /// The buffer needs the alignment of the values, which e.g. a `Vec<u8>` does not
+    /// guarantee.
+    pub fn load_from_buffer<T>(buffer: T) -> Result<Self>
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        Self::load_from_data(Data::new(buffer))
+    }
+
+    pub fn load_from_data(data: Data) -> Result<Self> {
+        let mut inner =
+            PerfectHashMapDeserializer::new(CHDReader::new(), DefaultHashValueReader::new());
+        inner.load_from_data(data)?;
+        if V::NATIVE_ENDIAN && inner.info().map(|info| info.endian) != Some(Endian::NATIVE) {
+            return Err(Error::InvalidConfig(
+                "pod values are only read in native endian",
+            ));
+        }
+        if !(inner.value_deserializer.payload().as_ptr() as usize).is_multiple_of(V::ALIGN) {
+            return Err(Error::Unaligned);
+        }
+        Ok(Self {
+            inner,
+            _pd: PhantomData,
+        })
+    }
+
+    /// Look up and decode the value of `key`, see [`PerfectHashMapDeserializer::get`].
+    pub fn get(&self, key: &K) -> Option<V::Ref<'_>> {
+        V::decode(self.inner.get(key)?)
+    }
+
+    /// Recompute the section checksums of the loaded file.
+    pub fn verify(&self) -> Result<()> {
+        self.inner.verify()
+    }
+}
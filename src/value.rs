@@ -1,92 +1,1152 @@
-use crate::{any_as_u8_mut_slice, any_as_u8_slice, PHashValueDeserializer, PHashValueSerializer};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::{Deref, Range};
+use std::sync::{Arc, Mutex};
 
+use crate::dictionary;
+use crate::elias_fano::{self, EliasFano};
+use crate::{
+    any_as_u8_mut_slice, any_as_u8_slice, Data, Endian, Error, PHashValueDeserializer,
+    PHashValueSerializer, Result, SECTION_ALIGN,
+};
+
+/// The top byte of `count` is the offset width in bytes, 4 or 8.
 #[derive(Default)]
-#[repr(packed)]
+#[repr(C, packed)]
 struct DefaultHeader {
     count: u64,
 }
 
-#[derive(Default)]
-pub struct DefaultHashValueWriter {}
+const OFFSET_WIDTH_SHIFT: u32 = 56;
+
+impl DefaultHeader {
+    fn new(count: usize, offset_width: usize, endian: Endian) -> Self {
+        let count = count as u64 | ((offset_width as u64) << OFFSET_WIDTH_SHIFT);
+        Self {
+            count: endian.convert_u64(count),
+        }
+    }
+
+    /// Number of values and offset width of a header read in `endian`.
+    fn parse(bytes: &[u8], endian: Endian) -> Result<(usize, usize)> {
+        let count = bytes
+            .get(..std::mem::size_of::<Self>())
+            .ok_or(Error::Corrupted("value section too small"))?;
+        let count = endian.convert_u64(u64::from_ne_bytes(count.try_into().unwrap()));
+        let offset_width = match count >> OFFSET_WIDTH_SHIFT {
+            4 => 4,
+            8 => 8,
+            _ => return Err(Error::Corrupted("unknown value offset width")),
+        };
+        let count = count & ((1 << OFFSET_WIDTH_SHIFT) - 1);
+        Ok((count as usize, offset_width))
+    }
+
+    /// Length of the header and the offsets, or `None` if it overflows.
+    fn index_len(count: usize, offset_width: usize) -> Option<usize> {
+        count
+            .checked_mul(offset_width)?
+            .checked_add(std::mem::size_of::<Self>())
+    }
+}
+
+pub struct DefaultHashValueWriter {
+    align: usize,
+}
+
+impl Default for DefaultHashValueWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl DefaultHashValueWriter {
     pub fn new() -> Self {
-        Self {}
+        Self { align: 1 }
+    }
+
+    /// Pad the offset table so the payload starts at a multiple of `align` bytes in the
+    /// file. A power of two up to 8.
+    pub fn align(mut self, align: usize) -> Self {
+        self.align = align;
+        self
     }
 }
 
 impl PHashValueSerializer for DefaultHashValueWriter {
-    fn write_all<W>(&self, values: &Vec<&[u8]>, writer: &mut W) -> Option<()>
+    const ID: u32 = 0;
+    type Prepared = ();
+
+    /// Offsets are 4 bytes while the values sum up to less than 4 GiB, and 8 bytes
+    /// otherwise.
+    fn write_index<W>(&self, values: &[&[u8]], writer: &mut W, endian: Endian) -> Result<()>
     where
         W: std::io::Write,
     {
-        let header = DefaultHeader {
-            count: values.len() as u64,
-        };
+        if !self.align.is_power_of_two() || self.align > SECTION_ALIGN as usize {
+            return Err(Error::InvalidConfig("align must be a power of two up to 8"));
+        }
+        if values.len() >= 1 << OFFSET_WIDTH_SHIFT {
+            return Err(Error::ValueTooLarge);
+        }
+        let total = values
+            .iter()
+            .try_fold(0u64, |sum, value| sum.checked_add(value.len() as u64))
+            .ok_or(Error::ValueTooLarge)?;
+        let offset_width = if total < u32::MAX as u64 { 4 } else { 8 };
+        let header = DefaultHeader::new(values.len(), offset_width, endian);
         unsafe {
-            writer.write(any_as_u8_slice(&header)).unwrap();
+            writer.write_all(any_as_u8_slice(&header))?;
         }
 
-        let mut sum = 0u32;
+        let mut sum = 0u64;
         for value in values {
-            let new_sum = value.len().checked_add(sum as usize)?;
-            if new_sum >= u32::MAX as usize {
-                return None;
-            }
-            sum = new_sum as u32;
+            sum += value.len() as u64;
             unsafe {
-                writer.write(any_as_u8_slice(&sum)).unwrap();
+                if offset_width == 4 {
+                    writer.write_all(any_as_u8_slice(&endian.convert_u32(sum as u32)))?;
+                } else {
+                    writer.write_all(any_as_u8_slice(&endian.convert_u64(sum)))?;
+                }
             }
         }
 
+        // the value section itself starts aligned
+        let len = std::mem::size_of::<DefaultHeader>() + values.len() * offset_width;
+        let padding = len.next_multiple_of(self.align) - len;
+        writer.write_all(&[0u8; SECTION_ALIGN as usize][..padding])?;
+        Ok(())
+    }
+
+    fn write_payload<W>(
+        &self,
+        values: &[&[u8]],
+        _prepared: (),
+        writer: &mut W,
+        _endian: Endian,
+    ) -> Result<()>
+    where
+        W: std::io::Write,
+    {
         for value in values {
-            writer.write(value).unwrap();
+            writer.write_all(value)?;
         }
 
-        Some(())
+        Ok(())
     }
 }
 
 pub struct DefaultHashValueReader {
-    header: DefaultHeader,
-    index_ptr: *const u32,
-    content_ptr: *const u8,
+    count: usize,
+    offset_width: usize,
+    offsets: Data,
+    content: Data,
+    endian: Endian,
+}
+
+impl Default for DefaultHashValueReader {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DefaultHashValueReader {
     pub fn new() -> Self {
         Self {
-            header: DefaultHeader::default(),
-            index_ptr: std::ptr::null(),
-            content_ptr: std::ptr::null(),
+            count: 0,
+            offset_width: 4,
+            offsets: Data::default(),
+            content: Data::default(),
+            endian: Endian::NATIVE,
+        }
+    }
+
+    pub(crate) fn payload(&self) -> &Data {
+        &self.content
+    }
+
+    /// Width of the offsets in bytes, 4 or 8.
+    pub fn offset_width(&self) -> usize {
+        self.offset_width
+    }
+
+    #[inline]
+    fn offset(&self, index: usize) -> usize {
+        if self.offset_width == 4 {
+            self.endian.read_u32(&self.offsets, index) as usize
+        } else {
+            self.endian.read_u64(&self.offsets, index) as usize
         }
     }
+
+    /// Load a section written by `write_all` without alignment, where the payload
+    /// directly follows the offsets.
+    pub(crate) fn load_section(&mut self, data: Data, endian: Endian) -> Result<()> {
+        let (count, offset_width) = DefaultHeader::parse(&data, endian)?;
+        let index_len = DefaultHeader::index_len(count, offset_width)
+            .filter(|len| *len <= data.len())
+            .ok_or(Error::Corrupted("value count out of bounds"))?;
+        self.load(
+            data.slice(0..index_len),
+            data.slice(index_len..data.len()),
+            endian,
+        )
+    }
 }
 
 impl PHashValueDeserializer for DefaultHashValueReader {
-    fn get<'a>(&'a self, index: crate::HashIndex) -> &'a [u8] {
-        debug_assert!(index < self.header.count as crate::HashIndex);
+    const ID: u32 = DefaultHashValueWriter::ID;
+
+    type Value<'a> = &'a [u8];
+
+    fn get(&self, index: crate::HashIndex) -> &[u8] {
+        debug_assert!((index as usize) < self.count);
+        let offset = self.offset(index as usize);
+        let offset_prev = if index > 0 {
+            self.offset(index as usize - 1)
+        } else {
+            0
+        };
+        // offsets are not validated at load time, keep corrupted ones inside the content
+        self.content.get(offset_prev..offset).unwrap_or_default()
+    }
+    fn load(&mut self, index: Data, payload: Data, endian: Endian) -> Result<()> {
+        let (count, offset_width) = DefaultHeader::parse(&index, endian)?;
+        let index_len = DefaultHeader::index_len(count, offset_width)
+            .filter(|len| *len <= index.len())
+            .ok_or(Error::Corrupted("value count out of bounds"))?;
+        self.count = count;
+        self.offset_width = offset_width;
+        self.endian = endian;
+        self.offsets = index.slice(std::mem::size_of::<DefaultHeader>()..index_len);
+        self.content = payload;
+        Ok(())
+    }
+    fn count(&self) -> usize {
+        self.count
+    }
+}
+
+#[derive(Default)]
+#[repr(C, packed)]
+struct FixedSizeHeader {
+    count: u64,
+    width: u64,
+}
+
+/// Stores values of one width back to back, so the value of slot `index` is found at
+/// `index * width` without an offset table.
+///
+/// Empty values mark unused slots and are stored as zeros.
+pub struct FixedSizeValueWriter {
+    align: usize,
+}
+
+impl Default for FixedSizeValueWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FixedSizeValueWriter {
+    pub fn new() -> Self {
+        Self { align: 1 }
+    }
+
+    /// Start every value at a multiple of `align` bytes in the file. A power of two up
+    /// to 8 which divides the value width.
+    pub fn align(mut self, align: usize) -> Self {
+        self.align = align;
+        self
+    }
+
+    fn width(values: &[&[u8]]) -> Result<usize> {
+        let mut width = 0;
+        for value in values.iter().filter(|value| !value.is_empty()) {
+            if width == 0 {
+                width = value.len();
+            } else if value.len() != width {
+                return Err(Error::WidthMismatch {
+                    expected: width,
+                    found: value.len(),
+                });
+            }
+        }
+        Ok(width)
+    }
+}
+
+impl PHashValueSerializer for FixedSizeValueWriter {
+    const ID: u32 = 1;
+    type Prepared = ();
+
+    fn write_index<W>(&self, values: &[&[u8]], writer: &mut W, endian: Endian) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        if !self.align.is_power_of_two() || self.align > SECTION_ALIGN as usize {
+            return Err(Error::InvalidConfig("align must be a power of two up to 8"));
+        }
+        let width = Self::width(values)?;
+        if !width.is_multiple_of(self.align) {
+            return Err(Error::InvalidConfig(
+                "value width must be a multiple of align",
+            ));
+        }
+        let header = FixedSizeHeader {
+            count: endian.convert_u64(values.len() as u64),
+            width: endian.convert_u64(width as u64),
+        };
         unsafe {
-            let offset = *self.index_ptr.add(index as usize);
-            if index > 0 {
-                let offset_prev = *self.index_ptr.add(index as usize - 1);
-                let size = offset - offset_prev;
-                std::slice::from_raw_parts(
-                    self.content_ptr.add(offset_prev as usize),
-                    size as usize,
-                )
+            writer.write_all(any_as_u8_slice(&header))?;
+        }
+        // the header is 16 bytes, already aligned to any supported `align`
+        Ok(())
+    }
+
+    fn write_payload<W>(
+        &self,
+        values: &[&[u8]],
+        _prepared: (),
+        writer: &mut W,
+        _endian: Endian,
+    ) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        let width = Self::width(values)?;
+        let zeros = vec![0u8; width];
+        for value in values {
+            if value.is_empty() {
+                writer.write_all(&zeros)?;
             } else {
-                std::slice::from_raw_parts(self.content_ptr, offset as usize)
+                writer.write_all(value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct FixedSizeValueReader {
+    header: FixedSizeHeader,
+    content: Data,
+}
+
+impl Default for FixedSizeValueReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FixedSizeValueReader {
+    pub fn new() -> Self {
+        Self {
+            header: FixedSizeHeader::default(),
+            content: Data::default(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.header.width as usize
+    }
+}
+
+impl PHashValueDeserializer for FixedSizeValueReader {
+    const ID: u32 = FixedSizeValueWriter::ID;
+
+    type Value<'a> = &'a [u8];
+
+    fn get(&self, index: crate::HashIndex) -> &[u8] {
+        debug_assert!(index < self.header.count as crate::HashIndex);
+        let width = self.header.width as usize;
+        let offset = index as usize * width;
+        self.content.get(offset..offset + width).unwrap_or_default()
+    }
+    fn load(&mut self, index: Data, payload: Data, endian: Endian) -> Result<()> {
+        let header_len = std::mem::size_of::<FixedSizeHeader>();
+        if index.len() < header_len {
+            return Err(Error::Corrupted("value section too small"));
+        }
+        unsafe {
+            any_as_u8_mut_slice(&mut self.header).copy_from_slice(&index[..header_len]);
+        }
+        self.header.count = endian.convert_u64(self.header.count);
+        self.header.width = endian.convert_u64(self.header.width);
+        (self.header.count as usize)
+            .checked_mul(self.header.width as usize)
+            .filter(|len| *len <= payload.len())
+            .ok_or(Error::Corrupted("value count out of bounds"))?;
+        self.content = payload;
+        Ok(())
+    }
+    fn count(&self) -> usize {
+        self.header.count as usize
+    }
+}
+
+/// Stores the value offsets Elias–Fano encoded, which takes about
+/// `2 + log2(average value length)` bits per slot instead of 32. Lookups decode two
+/// neighbouring offsets, which costs a few word scans more than an offset table.
+///
+/// The payload always starts at a multiple of 8 bytes in the file.
+#[derive(Default)]
+pub struct EliasFanoValueWriter;
+
+impl EliasFanoValueWriter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl PHashValueSerializer for EliasFanoValueWriter {
+    const ID: u32 = 2;
+    type Prepared = ();
+
+    fn write_index<W>(&self, values: &[&[u8]], writer: &mut W, endian: Endian) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        let mut offsets = Vec::with_capacity(values.len() + 1);
+        let mut sum = 0u64;
+        offsets.push(sum);
+        for value in values {
+            sum += value.len() as u64;
+            offsets.push(sum);
+        }
+        elias_fano::write(&offsets, writer, endian)
+    }
+
+    fn write_payload<W>(
+        &self,
+        values: &[&[u8]],
+        _prepared: (),
+        writer: &mut W,
+        _endian: Endian,
+    ) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        for value in values {
+            writer.write_all(value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct EliasFanoValueReader {
+    offsets: EliasFano,
+    content: Data,
+}
+
+impl EliasFanoValueReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PHashValueDeserializer for EliasFanoValueReader {
+    const ID: u32 = EliasFanoValueWriter::ID;
+
+    type Value<'a> = &'a [u8];
+
+    fn get(&self, index: crate::HashIndex) -> &[u8] {
+        debug_assert!((index as usize) < self.count());
+        let (beg, end) = self.offsets.get_pair(index as usize);
+        // offsets are not validated at load time, keep corrupted ones inside the content
+        self.content
+            .get(beg as usize..end as usize)
+            .unwrap_or_default()
+    }
+    fn load(&mut self, index: Data, payload: Data, endian: Endian) -> Result<()> {
+        let (offsets, _) = EliasFano::load(&index, endian)?;
+        if offsets.len() == 0 {
+            return Err(Error::Corrupted("value count out of bounds"));
+        }
+        self.offsets = offsets;
+        self.content = payload;
+        Ok(())
+    }
+    fn count(&self) -> usize {
+        self.offsets.len() - 1
+    }
+}
+
+#[derive(Default)]
+#[repr(C, packed)]
+struct DedupHeader {
+    count: u64,
+    distinct: u64,
+}
+
+/// Figures of a section written by [`DedupValueWriter`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DedupStats {
+    /// Slots with a non-empty value.
+    pub values: u64,
+    /// Distinct non-empty values.
+    pub distinct: u64,
+    /// Bytes of all values, as an encoding without deduplication stores them.
+    pub total_bytes: u64,
+    /// Bytes of the distinct values.
+    pub stored_bytes: u64,
+}
+
+impl DedupStats {
+    /// The figures of a section of `values`.
+    pub fn new(values: &[&[u8]]) -> Self {
+        let non_empty = values.iter().filter(|value| !value.is_empty());
+        let distinct: HashSet<&[u8]> = non_empty.clone().copied().collect();
+        Self {
+            values: non_empty.clone().count() as u64,
+            distinct: distinct.len() as u64,
+            total_bytes: non_empty.map(|value| value.len() as u64).sum(),
+            stored_bytes: distinct.iter().map(|value| value.len() as u64).sum(),
+        }
+    }
+
+    pub fn saved_bytes(&self) -> u64 {
+        self.total_bytes - self.stored_bytes
+    }
+}
+
+/// Stores every distinct value once. Each slot holds the `u32` number of its value,
+/// which is located through a `u64` offset table of the distinct values.
+///
+/// Pays off when many keys share values, e.g. category names; with mostly unique
+/// values the extra indirection only costs space.
+#[derive(Default)]
+pub struct DedupValueWriter;
+
+impl DedupValueWriter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// The distinct value number of every slot, and the slot of each distinct value.
+    /// Empty values are number 0.
+    fn dedup(values: &[&[u8]]) -> Result<(Vec<u32>, Vec<usize>)> {
+        let mut numbers = HashMap::new();
+        numbers.insert(&[][..], 0u32);
+        let mut slots = vec![usize::MAX];
+        let mut ids = Vec::with_capacity(values.len());
+        for (slot, value) in values.iter().enumerate() {
+            let next = slots.len();
+            let id = *numbers.entry(*value).or_insert_with(|| {
+                slots.push(slot);
+                next as u32
+            });
+            if slots.len() > u32::MAX as usize {
+                return Err(Error::ValueTooLarge);
+            }
+            ids.push(id);
+        }
+        Ok((ids, slots))
+    }
+}
+
+impl PHashValueSerializer for DedupValueWriter {
+    const ID: u32 = 3;
+    type Prepared = Vec<usize>;
+
+    fn write_index<W>(
+        &self,
+        values: &[&[u8]],
+        writer: &mut W,
+        endian: Endian,
+    ) -> Result<Self::Prepared>
+    where
+        W: std::io::Write,
+    {
+        let (ids, slots) = Self::dedup(values)?;
+        let header = DedupHeader {
+            count: endian.convert_u64(ids.len() as u64),
+            distinct: endian.convert_u64(slots.len() as u64),
+        };
+        unsafe {
+            writer.write_all(any_as_u8_slice(&header))?;
+        }
+        for id in &ids {
+            writer.write_all(&endian.convert_u32(*id).to_ne_bytes())?;
+        }
+        // keep the offsets 8 byte aligned
+        if ids.len() % 2 == 1 {
+            writer.write_all(&[0u8; 4])?;
+        }
+
+        // the empty value spans 0..0
+        let mut offset = 0u64;
+        writer.write_all(&[0u8; 16])?;
+        for slot in &slots[1..] {
+            offset += values[*slot].len() as u64;
+            writer.write_all(&endian.convert_u64(offset).to_ne_bytes())?;
+        }
+        // the slot of each distinct value, whose bytes make up the payload
+        Ok(slots)
+    }
+
+    fn write_payload<W>(
+        &self,
+        values: &[&[u8]],
+        slots: Self::Prepared,
+        writer: &mut W,
+        _endian: Endian,
+    ) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        for slot in &slots[1..] {
+            writer.write_all(values[*slot])?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct DedupValueReader {
+    count: usize,
+    distinct: usize,
+    ids: Data,
+    offsets: Data,
+    content: Data,
+    endian: Endian,
+}
+
+impl DedupValueReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct non-empty values.
+    pub fn distinct_count(&self) -> usize {
+        self.distinct.saturating_sub(1)
+    }
+}
+
+impl PHashValueDeserializer for DedupValueReader {
+    const ID: u32 = DedupValueWriter::ID;
+
+    type Value<'a> = &'a [u8];
+
+    fn get(&self, index: crate::HashIndex) -> &[u8] {
+        debug_assert!((index as usize) < self.count);
+        let id = self.endian.read_u32(&self.ids, index as usize) as usize;
+        if id >= self.distinct {
+            return &[];
+        }
+        let beg = self.endian.read_u64(&self.offsets, id) as usize;
+        let end = self.endian.read_u64(&self.offsets, id + 1) as usize;
+        // offsets are not validated at load time, keep corrupted ones inside the content
+        self.content.get(beg..end).unwrap_or_default()
+    }
+    fn load(&mut self, index: Data, payload: Data, endian: Endian) -> Result<()> {
+        let mut header = DedupHeader::default();
+        let header_len = std::mem::size_of::<DedupHeader>();
+        if index.len() < header_len {
+            return Err(Error::Corrupted("value section too small"));
+        }
+        unsafe {
+            any_as_u8_mut_slice(&mut header).copy_from_slice(&index[..header_len]);
+        }
+        let count = endian.convert_u64(header.count) as usize;
+        let distinct = endian.convert_u64(header.distinct) as usize;
+        let ids_len = count
+            .checked_mul(std::mem::size_of::<u32>())
+            .map(|len| len.next_multiple_of(8))
+            .ok_or(Error::Corrupted("value count out of bounds"))?;
+        let offsets_len = distinct
+            .checked_add(1)
+            .and_then(|len| len.checked_mul(std::mem::size_of::<u64>()))
+            .ok_or(Error::Corrupted("value count out of bounds"))?;
+        if distinct == 0
+            || ids_len
+                .checked_add(offsets_len)
+                .filter(|len| *len <= index.len() - header_len)
+                .is_none()
+        {
+            return Err(Error::Corrupted("value count out of bounds"));
+        }
+        self.count = count;
+        self.distinct = distinct;
+        self.ids = index.slice(header_len..header_len + count * 4);
+        self.offsets = index.slice(header_len + ids_len..header_len + ids_len + offsets_len);
+        self.content = payload;
+        self.endian = endian;
+        Ok(())
+    }
+    fn count(&self) -> usize {
+        self.count
+    }
+}
+
+#[derive(Default)]
+#[repr(C, packed)]
+struct BlockHeader {
+    count: u64,
+    block_slots: u64,
+    blocks: u64,
+    /// Bytes of the largest block before compression.
+    max_block: u64,
+}
+
+/// Groups slots into blocks of `block_slots` values and compresses every block with
+/// LZ4, for maps where size matters more than lookup time.
+///
+/// A block holds the `u32` end offsets of its values followed by the values. Lookups
+/// decompress the whole block, see [`BlockCompressedValueReader`].
+pub struct BlockCompressedValueWriter {
+    block_slots: usize,
+}
+
+impl Default for BlockCompressedValueWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockCompressedValueWriter {
+    pub fn new() -> Self {
+        Self { block_slots: 64 }
+    }
+
+    /// Slots per block. Larger blocks compress better but take longer to decompress.
+    pub fn block_slots(mut self, block_slots: usize) -> Self {
+        self.block_slots = block_slots;
+        self
+    }
+}
+
+impl PHashValueSerializer for BlockCompressedValueWriter {
+    const ID: u32 = 4;
+    type Prepared = Vec<u8>;
+
+    fn write_index<W>(
+        &self,
+        values: &[&[u8]],
+        writer: &mut W,
+        endian: Endian,
+    ) -> Result<Self::Prepared>
+    where
+        W: std::io::Write,
+    {
+        if self.block_slots == 0 || self.block_slots > u32::MAX as usize {
+            return Err(Error::InvalidConfig("block_slots must be 1..=u32::MAX"));
+        }
+        let mut compressed = Vec::new();
+        let mut offsets = vec![0u64];
+        let mut max_block = 0;
+        let mut block = Vec::new();
+        for chunk in values.chunks(self.block_slots) {
+            block.clear();
+            let mut end = 0u32;
+            for value in chunk {
+                end = u32::try_from(value.len())
+                    .ok()
+                    .and_then(|len| end.checked_add(len))
+                    .ok_or(Error::ValueTooLarge)?;
+                block.extend_from_slice(&endian.convert_u32(end).to_ne_bytes());
             }
+            for value in chunk {
+                block.extend_from_slice(value);
+            }
+            compressed.extend_from_slice(&lz4_flex::block::compress_prepend_size(&block));
+            offsets.push(compressed.len() as u64);
+            max_block = max_block.max(block.len());
         }
+
+        let header = BlockHeader {
+            count: endian.convert_u64(values.len() as u64),
+            block_slots: endian.convert_u64(self.block_slots as u64),
+            blocks: endian.convert_u64(offsets.len() as u64 - 1),
+            max_block: endian.convert_u64(max_block as u64),
+        };
+        unsafe {
+            writer.write_all(any_as_u8_slice(&header))?;
+        }
+        for offset in offsets {
+            writer.write_all(&endian.convert_u64(offset).to_ne_bytes())?;
+        }
+        Ok(compressed)
+    }
+
+    fn write_payload<W>(
+        &self,
+        _values: &[&[u8]],
+        compressed: Self::Prepared,
+        writer: &mut W,
+        _endian: Endian,
+    ) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        writer.write_all(&compressed)?;
+        Ok(())
+    }
+}
+
+/// Decompress the output of `compress_prepend_size` against `dict`, `None` if it is
+/// corrupted or its size prefix exceeds `max_len`, so a corrupted file cannot make us
+/// allocate more.
+fn decompress_prepended(compressed: &[u8], max_len: usize, dict: &[u8]) -> Option<Vec<u8>> {
+    let (len, compressed) = compressed.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if len > max_len {
+        return None;
+    }
+    let mut out = vec![0u8; len];
+    let written = lz4_flex::block::decompress_into_with_dict(compressed, &mut out, dict).ok()?;
+    (written == len).then_some(out)
+}
+
+/// A value of [`BlockCompressedValueReader`], which shares its decompressed block.
+#[derive(Debug, Clone)]
+pub struct BlockValue {
+    block: Arc<[u8]>,
+    range: Range<usize>,
+}
+
+impl Deref for BlockValue {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.block[self.range.clone()]
+    }
+}
+
+impl<'a> From<BlockValue> for Cow<'a, [u8]> {
+    fn from(value: BlockValue) -> Self {
+        Cow::Owned(value.to_vec())
     }
-    fn load<'a>(&'a mut self, ptr: &'a [u8]) -> Option<()> {
+}
+
+/// Recently decompressed blocks, the most recent first.
+#[derive(Default)]
+struct BlockCache {
+    blocks: VecDeque<(usize, Arc<[u8]>)>,
+}
+
+impl BlockCache {
+    fn get(&mut self, block: usize) -> Option<Arc<[u8]>> {
+        let pos = self.blocks.iter().position(|(index, _)| *index == block)?;
+        let entry = self.blocks.remove(pos)?;
+        let data = entry.1.clone();
+        self.blocks.push_front(entry);
+        Some(data)
+    }
+
+    fn insert(&mut self, block: usize, data: Arc<[u8]>, capacity: usize) {
+        if self.blocks.len() == capacity {
+            self.blocks.pop_back();
+        }
+        self.blocks.push_front((block, data));
+    }
+}
+
+pub struct BlockCompressedValueReader {
+    count: usize,
+    block_slots: usize,
+    max_block: usize,
+    offsets: Data,
+    content: Data,
+    endian: Endian,
+    cache_blocks: usize,
+    cache: Mutex<BlockCache>,
+}
+
+impl Default for BlockCompressedValueReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockCompressedValueReader {
+    pub fn new() -> Self {
+        Self {
+            count: 0,
+            block_slots: 1,
+            max_block: 0,
+            offsets: Data::default(),
+            content: Data::default(),
+            endian: Endian::NATIVE,
+            cache_blocks: 8,
+            cache: Mutex::new(BlockCache::default()),
+        }
+    }
+
+    /// Keep up to `blocks` decompressed blocks for repeated lookups, 8 by default. 0
+    /// decompresses on every lookup.
+    pub fn cache_blocks(mut self, blocks: usize) -> Self {
+        self.cache_blocks = blocks;
+        self
+    }
+
+    fn block(&self, block: usize) -> Arc<[u8]> {
+        if self.cache_blocks == 0 {
+            return self
+                .decompress(block)
+                .expect("blocks are checked on load")
+                .into();
+        }
+        if let Some(data) = self.cache.lock().unwrap().get(block) {
+            return data;
+        }
+        // decompress without the lock, so lookups of other blocks go on meanwhile
+        let data: Arc<[u8]> = self
+            .decompress(block)
+            .expect("blocks are checked on load")
+            .into();
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(block, data.clone(), self.cache_blocks);
+        data
+    }
+
+    /// The decompressed `block`, `None` if it is corrupted.
+    fn decompress(&self, block: usize) -> Option<Vec<u8>> {
+        let beg = self.endian.read_u64(&self.offsets, block) as usize;
+        let end = self.endian.read_u64(&self.offsets, block + 1) as usize;
+        decompress_prepended(self.content.get(beg..end)?, self.max_block, &[])
+    }
+
+    /// Number of values in `block`, only the last one is not full.
+    fn block_len(&self, block: usize) -> usize {
+        self.block_slots.min(self.count - block * self.block_slots)
+    }
+}
+
+impl PHashValueDeserializer for BlockCompressedValueReader {
+    const ID: u32 = BlockCompressedValueWriter::ID;
+
+    type Value<'a> = BlockValue;
+
+    fn get(&self, index: crate::HashIndex) -> BlockValue {
+        debug_assert!((index as usize) < self.count);
+        let (block, slot) = (
+            index as usize / self.block_slots,
+            index as usize % self.block_slots,
+        );
+        let data = self.block(block);
+        let slots = self.block_len(block);
+        let end_of = |slot: usize| {
+            let end = u32::from_ne_bytes(data[slot * 4..slot * 4 + 4].try_into().unwrap());
+            self.endian.convert_u32(end) as usize + slots * 4
+        };
+        let beg = if slot > 0 {
+            end_of(slot - 1)
+        } else {
+            slots * 4
+        };
+        let range = beg..end_of(slot);
+        BlockValue { block: data, range }
+    }
+    fn load(&mut self, index: Data, payload: Data, endian: Endian) -> Result<()> {
+        let mut header = BlockHeader::default();
+        let header_len = std::mem::size_of::<BlockHeader>();
+        if index.len() < header_len {
+            return Err(Error::Corrupted("value section too small"));
+        }
         unsafe {
-            let desc = any_as_u8_mut_slice(&mut self.header);
-            std::ptr::copy(ptr.as_ptr(), desc.as_mut_ptr(), desc.len());
-            self.index_ptr = ptr.as_ptr().add(std::mem::size_of::<DefaultHeader>()) as *const u32;
-            self.content_ptr = self.index_ptr.add(self.header.count as usize) as *const u8;
+            any_as_u8_mut_slice(&mut header).copy_from_slice(&index[..header_len]);
+        }
+        let count = endian.convert_u64(header.count) as usize;
+        let block_slots = endian.convert_u64(header.block_slots) as usize;
+        let blocks = endian.convert_u64(header.blocks) as usize;
+        let max_block = endian.convert_u64(header.max_block) as usize;
+        if block_slots == 0 || count.div_ceil(block_slots) != blocks {
+            return Err(Error::Corrupted("value block count mismatch"));
+        }
+        let offsets_len = (blocks + 1)
+            .checked_mul(std::mem::size_of::<u64>())
+            .filter(|len| *len <= index.len() - header_len)
+            .ok_or(Error::Corrupted("value count out of bounds"))?;
+        self.count = count;
+        self.block_slots = block_slots;
+        self.max_block = max_block;
+        self.offsets = index.slice(header_len..header_len + offsets_len);
+        self.content = payload;
+        self.endian = endian;
+        self.cache.get_mut().unwrap().blocks.clear();
+
+        // decompress every block once, so lookups never meet a corrupted one
+        for block in 0..blocks {
+            let data = self
+                .decompress(block)
+                .ok_or(Error::Corrupted("invalid value block"))?;
+            let slots = self.block_len(block);
+            let ends = data
+                .get(..slots * 4)
+                .ok_or(Error::Corrupted("value block too small"))?;
+            let mut prev = 0;
+            for end in ends.chunks_exact(4) {
+                let end = endian.convert_u32(u32::from_ne_bytes(end.try_into().unwrap()));
+                if end < prev {
+                    return Err(Error::Corrupted("value block offsets out of order"));
+                }
+                prev = end;
+            }
+            if slots * 4 + prev as usize != data.len() {
+                return Err(Error::Corrupted("value block size mismatch"));
+            }
+        }
+        Ok(())
+    }
+    fn count(&self) -> usize {
+        self.count
+    }
+
+    fn unloaded(&self) -> Self {
+        Self::default().cache_blocks(self.cache_blocks)
+    }
+}
+
+/// Compresses every value on its own with LZ4 against a dictionary trained from a
+/// sample of all values, which suits many short, similar values such as JSON
+/// fragments better than compressing blocks of slots.
+///
+/// The section holds the dictionary length, the length of the largest value, the
+/// dictionary and then the compressed values in the layout of [`DefaultHashValueWriter`].
+pub struct DictionaryValueWriter {
+    dict_size: usize,
+}
+
+impl Default for DictionaryValueWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DictionaryValueWriter {
+    pub fn new() -> Self {
+        Self {
+            dict_size: 16 << 10,
+        }
+    }
+
+    /// Maximum dictionary size in bytes, 16 KiB by default. LZ4 only refers back
+    /// 64 KiB, so larger dictionaries are not useful.
+    pub fn dict_size(mut self, dict_size: usize) -> Self {
+        self.dict_size = dict_size;
+        self
+    }
+}
+
+impl PHashValueSerializer for DictionaryValueWriter {
+    const ID: u32 = 5;
+    type Prepared = Vec<Vec<u8>>;
+
+    fn write_index<W>(
+        &self,
+        values: &[&[u8]],
+        writer: &mut W,
+        endian: Endian,
+    ) -> Result<Self::Prepared>
+    where
+        W: std::io::Write,
+    {
+        if self.dict_size > u16::MAX as usize + 1 {
+            return Err(Error::InvalidConfig("dict_size must be at most 64 KiB"));
+        }
+        let dict = dictionary::train(values, self.dict_size);
+        let max_len = values.iter().map(|value| value.len()).max().unwrap_or(0);
+        writer.write_all(&endian.convert_u64(dict.len() as u64).to_ne_bytes())?;
+        writer.write_all(&endian.convert_u64(max_len as u64).to_ne_bytes())?;
+        writer.write_all(&dict)?;
+        let padding = dict.len().next_multiple_of(SECTION_ALIGN as usize) - dict.len();
+        writer.write_all(&[0u8; SECTION_ALIGN as usize][..padding])?;
+
+        // empty values, e.g. of unused slots, stay empty
+        let compressed: Vec<Vec<u8>> = values
+            .iter()
+            .map(|value| match value.is_empty() {
+                true => Vec::new(),
+                false => lz4_flex::block::compress_prepend_size_with_dict(value, &dict),
+            })
+            .collect();
+        let slices: Vec<&[u8]> = compressed.iter().map(Vec::as_slice).collect();
+        DefaultHashValueWriter::new().write_index(&slices, writer, endian)?;
+        Ok(compressed)
+    }
+
+    fn write_payload<W>(
+        &self,
+        _values: &[&[u8]],
+        compressed: Self::Prepared,
+        writer: &mut W,
+        _endian: Endian,
+    ) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        for value in compressed {
+            writer.write_all(&value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct DictionaryValueReader {
+    dict: Data,
+    max_len: usize,
+    values: DefaultHashValueReader,
+}
+
+impl DictionaryValueReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dictionary(&self) -> &[u8] {
+        &self.dict
+    }
+}
+
+impl PHashValueDeserializer for DictionaryValueReader {
+    const ID: u32 = DictionaryValueWriter::ID;
+
+    type Value<'a> = Vec<u8>;
+
+    fn get(&self, index: crate::HashIndex) -> Vec<u8> {
+        let compressed = self.values.get(index);
+        if compressed.is_empty() {
+            return Vec::new();
         }
-        Some(())
+        decompress_prepended(compressed, self.max_len, &self.dict)
+            .expect("values are checked on load")
+    }
+    fn load(&mut self, index: Data, payload: Data, endian: Endian) -> Result<()> {
+        if index.len() < 16 {
+            return Err(Error::Corrupted("value section too small"));
+        }
+        let (dict_len, max_len) = (endian.read_u64(&index, 0), endian.read_u64(&index, 1));
+        let dict_end = (dict_len as usize)
+            .checked_add(16)
+            .filter(|end| *end <= index.len())
+            .ok_or(Error::Corrupted("dictionary out of bounds"))?;
+        let values_beg = dict_end
+            .next_multiple_of(SECTION_ALIGN as usize)
+            .min(index.len());
+        self.dict = index.slice(16..dict_end);
+        self.max_len = max_len as usize;
+        self.values
+            .load(index.slice(values_beg..index.len()), payload, endian)?;
+
+        // decompress every value once, so lookups never meet a corrupted one
+        for index in 0..self.values.count() as crate::HashIndex {
+            let compressed = self.values.get(index);
+            if !compressed.is_empty()
+                && decompress_prepended(compressed, self.max_len, &self.dict).is_none()
+            {
+                return Err(Error::Corrupted("invalid compressed value"));
+            }
+        }
+        Ok(())
+    }
+    fn count(&self) -> usize {
+        self.values.count()
     }
 }
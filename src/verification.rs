@@ -0,0 +1,198 @@
+use crate::value::{DefaultHashValueReader, DefaultHashValueWriter};
+use crate::{
+    Data, Endian, Error, HashIndex, Hasher, PHashKey, PHashValueDeserializer, PHashValueSerializer,
+    Result,
+};
+
+pub(crate) const FLAG_VERIFY_MASK: u32 = 0b11;
+pub(crate) const FLAG_VERIFY_FINGERPRINT: u32 = 1;
+pub(crate) const FLAG_VERIFY_FULL_KEY: u32 = 2;
+pub(crate) const FLAG_FINGERPRINT_WIDTH_SHIFT: u32 = 8;
+pub(crate) const FLAG_FINGERPRINT_WIDTH_MASK: u32 = 0xFF << FLAG_FINGERPRINT_WIDTH_SHIFT;
+
+const FINGERPRINT_SALT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// How a map proves that a looked up key was one of the keys it was built from.
+///
+/// Without verification every key is mapped to some slot, so absent keys return
+/// the value of an unrelated entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyVerification {
+    #[default]
+    None,
+    /// Store a fingerprint of `1..=8` bytes per slot. Absent keys are rejected
+    /// except with probability `2^-(8 * bytes)`.
+    Fingerprint(u8),
+    /// Store the full key of every slot and which slots are used. Absent keys are
+    /// always rejected.
+    FullKey,
+}
+
+impl KeyVerification {
+    pub(crate) fn flag(&self) -> u32 {
+        match self {
+            KeyVerification::None => 0,
+            KeyVerification::Fingerprint(width) => {
+                FLAG_VERIFY_FINGERPRINT | ((*width as u32) << FLAG_FINGERPRINT_WIDTH_SHIFT)
+            }
+            KeyVerification::FullKey => FLAG_VERIFY_FULL_KEY,
+        }
+    }
+
+    pub(crate) fn from_flag(flag: u32) -> Option<Self> {
+        match flag & FLAG_VERIFY_MASK {
+            0 => Some(KeyVerification::None),
+            FLAG_VERIFY_FINGERPRINT => {
+                let width = (flag & FLAG_FINGERPRINT_WIDTH_MASK) >> FLAG_FINGERPRINT_WIDTH_SHIFT;
+                if (1..=8).contains(&width) {
+                    Some(KeyVerification::Fingerprint(width as u8))
+                } else {
+                    None
+                }
+            }
+            FLAG_VERIFY_FULL_KEY => Some(KeyVerification::FullKey),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn key_fingerprint<K: PHashKey + ?Sized, H: Hasher>(key: &K) -> u64 {
+    // hashers hash the concatenation of their writes, so this hashes salt and key
+    // as one buffer
+    let mut hasher = H::default();
+    hasher.write(&FINGERPRINT_SALT.to_le_bytes());
+    hasher.write(key.as_key_bytes().as_ref());
+    let hash = hasher.finish_u128();
+    ((hash >> 64) as u64) ^ (hash as u64)
+}
+
+pub(crate) fn write_verification<K, H, W>(
+    verification: KeyVerification,
+    keys: &[Option<&K>],
+    writer: &mut W,
+    endian: Endian,
+) -> Result<()>
+where
+    K: PHashKey,
+    H: Hasher,
+    W: std::io::Write,
+{
+    match verification {
+        KeyVerification::None => {}
+        KeyVerification::Fingerprint(width) => {
+            for key in keys {
+                let fingerprint = key.map(key_fingerprint::<K, H>).unwrap_or(0);
+                writer.write_all(&fingerprint.to_le_bytes()[..width as usize])?;
+            }
+        }
+        KeyVerification::FullKey => {
+            // unused slots store an empty key, so record them to tell absent empty keys
+            let mut used = vec![0u64; keys.len().div_ceil(64)];
+            for (slot, key) in keys.iter().enumerate() {
+                if key.is_some() {
+                    used[slot / 64] |= 1 << (slot % 64);
+                }
+            }
+            for word in used {
+                writer.write_all(&endian.convert_u64(word).to_ne_bytes())?;
+            }
+            let bytes: Vec<Option<K::Bytes<'_>>> = keys
+                .iter()
+                .map(|key| key.map(|key| key.as_key_bytes()))
+                .collect();
+            let bytes: Vec<&[u8]> = bytes
+                .iter()
+                .map(|v| v.as_ref().map_or(&[][..], |v| v.as_ref()))
+                .collect();
+            DefaultHashValueWriter::new().write_all(&bytes, writer, endian)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) struct KeyVerifier {
+    verification: KeyVerification,
+    fingerprints: Data,
+    /// Bit per slot, set for the slots which hold a key.
+    used: Data,
+    keys: DefaultHashValueReader,
+    endian: Endian,
+}
+
+impl KeyVerifier {
+    pub(crate) fn new() -> Self {
+        Self {
+            verification: KeyVerification::None,
+            fingerprints: Data::default(),
+            used: Data::default(),
+            keys: DefaultHashValueReader::new(),
+            endian: Endian::NATIVE,
+        }
+    }
+
+    pub(crate) fn load(
+        &mut self,
+        verification: KeyVerification,
+        data: Data,
+        max_hash_index: HashIndex,
+        endian: Endian,
+    ) -> Result<()> {
+        self.verification = verification;
+        self.endian = endian;
+        match verification {
+            KeyVerification::None => {}
+            KeyVerification::Fingerprint(width) => {
+                if data.len() / (width as usize) < max_hash_index as usize {
+                    return Err(Error::Corrupted("fingerprints out of bounds"));
+                }
+                self.fingerprints = data;
+            }
+            KeyVerification::FullKey => {
+                let used_len = (max_hash_index as usize).div_ceil(64) * 8;
+                if data.len() < used_len {
+                    return Err(Error::Corrupted("used slots out of bounds"));
+                }
+                self.used = data.slice(0..used_len);
+                self.keys
+                    .load_section(data.slice(used_len..data.len()), endian)?;
+                if self.keys.count() < max_hash_index as usize {
+                    return Err(Error::Corrupted("stored keys out of bounds"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The stored key of slot `index`, only known with [`KeyVerification::FullKey`]
+    /// and for used slots.
+    pub(crate) fn key(&self, index: HashIndex) -> Option<&[u8]> {
+        match self.verification {
+            KeyVerification::FullKey if self.used(index) => Some(self.keys.get(index)),
+            _ => None,
+        }
+    }
+
+    fn used(&self, index: HashIndex) -> bool {
+        let word = self.endian.read_u64(&self.used, index as usize / 64);
+        word & (1 << (index % 64)) != 0
+    }
+
+    pub(crate) fn verify<K: PHashKey + ?Sized, H: Hasher>(
+        &self,
+        index: HashIndex,
+        key: &K,
+    ) -> bool {
+        match self.verification {
+            KeyVerification::None => true,
+            KeyVerification::Fingerprint(width) => {
+                let width = width as usize;
+                let beg = index as usize * width;
+                let fingerprint = key_fingerprint::<K, H>(key).to_le_bytes();
+                self.fingerprints[beg..beg + width] == fingerprint[..width]
+            }
+            KeyVerification::FullKey => {
+                self.used(index) && self.keys.get(index) == key.as_key_bytes().as_ref()
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+#![cfg(feature = "cli")]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn phash(args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_phash"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// A directory of its own for every test, removed when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("phash-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn build_get_verify_test() {
+    let dir = TempDir::new("roundtrip");
+    let input = dir.0.join("input.tsv");
+    let output = dir.0.join("map.bin");
+    let lines: String = (0..100)
+        .map(|i| format!("key-{}\tvalue-{}\n", i, i))
+        .collect();
+    std::fs::write(&input, lines).unwrap();
+
+    let out = phash(&[
+        "build".as_ref(),
+        input.as_ref(),
+        "-o".as_ref(),
+        output.as_ref(),
+        "--verification".as_ref(),
+        "full".as_ref(),
+    ]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let out = phash(&[
+        "get".as_ref(),
+        output.as_ref(),
+        "key-7".as_ref(),
+        "key-42".as_ref(),
+    ]);
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"value-7\nvalue-42\n");
+
+    let out = phash(&["get".as_ref(), output.as_ref(), "absent".as_ref()]);
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty());
+
+    let out = phash(&["verify".as_ref(), output.as_ref()]);
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"ok\n");
+
+    let out = phash(&["dump".as_ref(), output.as_ref()]);
+    assert!(out.status.success());
+    let mut lines: Vec<&str> = std::str::from_utf8(&out.stdout).unwrap().lines().collect();
+    lines.sort();
+    let mut expected: Vec<String> = (0..100)
+        .map(|i| format!("key-{}\tvalue-{}", i, i))
+        .collect();
+    expected.sort();
+    assert_eq!(lines, expected);
+
+    // a flipped value byte fails the checksum
+    let mut data = std::fs::read(&output).unwrap();
+    let pos = data.windows(8).position(|w| w == b"value-42").unwrap();
+    data[pos] ^= 1;
+    std::fs::write(&output, data).unwrap();
+    let out = phash(&["verify".as_ref(), output.as_ref()]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn invalid_utf8_key_test() {
+    let dir = TempDir::new("utf8");
+    let input = dir.0.join("input.tsv");
+    let output = dir.0.join("map.bin");
+    std::fs::write(&input, b"good\t1\nba\xffd\t2\n").unwrap();
+
+    let out = phash(&[
+        "build".as_ref(),
+        input.as_ref(),
+        "-o".as_ref(),
+        output.as_ref(),
+    ]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("not valid UTF-8"));
+    assert!(!output.exists());
+}